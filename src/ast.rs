@@ -1,6 +1,14 @@
-use lasso::Spur;
+use std::fmt::Display;
+use std::hash::{Hash, Hasher};
 
-use crate::{bump::Node, token::Symbol, Spanned};
+use chumsky::span::SimpleSpan;
+use lasso::{Rodeo, Spur};
+
+use crate::{
+    bump::{BumpMap, Node},
+    token::Symbol,
+    Spanned,
+};
 
 pub type Module = Node<Spanned<Block>>;
 
@@ -11,8 +19,11 @@ pub enum Expr {
     While(While),
     For(For),
     Loop(Loop),
+    // with open(path) as f
+    //   ...
+    With(With),
     Continue,
-    Break(Option<Node<Spanned<Expr>>>),
+    Break(Break),
     Return(Option<Node<Spanned<Expr>>>),
     Paren(Node<Spanned<Expr>>),
     // fn name(x: ty) -> ty
@@ -27,8 +38,12 @@ pub enum Expr {
     StructInit(StructInit),
     ListInit(ListInit),
 
+    // `self` inside a method with a self parameter
+    SelfRef,
     // var
     Ident(Spur),
+    // `_` in pattern position, as in `let _ = f()` or `for _ in xs`
+    Wildcard,
     // 12 | 0xc | 0b1100
     Int(i64),
     // 1.0 | 1.0e10 | 1.0e-10
@@ -49,12 +64,71 @@ pub enum Expr {
     Access(Access),
     // var[x]
     Index(Index),
+    // var = x | var += x
+    Assign(Assign),
+}
+
+impl Expr {
+    /// Whether the expression names a place that can be assigned to.
+    pub fn is_lvalue(&self) -> bool {
+        matches!(self, Expr::Ident(_) | Expr::Access(_) | Expr::Index(_))
+    }
 }
 
 pub struct ItemPath {
     pub items: Vec<Spanned<PathPart>>,
 }
 
+// Paths compare by their parts alone, so the same type named in two places is equal.
+impl PartialEq for ItemPath {
+    fn eq(&self, other: &Self) -> bool {
+        self.items.len() == other.items.len()
+            && self.items.iter().zip(&other.items).all(|(a, b)| a.0 == b.0)
+    }
+}
+
+impl Eq for ItemPath {}
+
+impl Hash for ItemPath {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.items.len().hash(state);
+        for (part, _) in &self.items {
+            part.hash(state);
+        }
+    }
+}
+
+impl ItemPath {
+    /// Renders the path as written in an import, like `std:time`, resolving names
+    /// through `rodeo`.
+    pub fn display<'p>(&'p self, rodeo: &'p Rodeo) -> DisplayPath<'p> {
+        DisplayPath { path: self, rodeo }
+    }
+}
+
+pub struct DisplayPath<'p> {
+    path: &'p ItemPath,
+    rodeo: &'p Rodeo,
+}
+
+impl Display for DisplayPath<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (part, _)) in self.path.items.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            match part {
+                PathPart::Name(name) => write!(f, "{}", self.rodeo.resolve(name)),
+                PathPart::Self_ => write!(f, "self"),
+                PathPart::Super => write!(f, "super"),
+                PathPart::Root => write!(f, "root"),
+            }?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
 pub enum PathPart {
     // `foo` and `bar` in `self::foo::bar`
     Name(Spur),
@@ -73,6 +147,7 @@ pub struct Import {
     pub alias: Option<Spur>,
 }
 
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub enum TypeSig {
     Unit,
     Int,
@@ -82,10 +157,12 @@ pub enum TypeSig {
     List(Box<TypeSig>),
     Tuple(Vec<TypeSig>),
     Func(Vec<TypeSig>, Box<TypeSig>),
+    Optional(Box<TypeSig>),
     Struct(Vec<(Spur, TypeSig)>),
     Enum(Vec<(Spur, TypeSig)>),
 }
 
+#[derive(PartialEq, Eq, Hash)]
 pub enum TypeName {
     Unit,
     Int,
@@ -95,8 +172,24 @@ pub enum TypeName {
     Tuple(Vec<TypeName>),
     List(Box<TypeName>),
     Func(Vec<TypeName>, Option<Box<TypeName>>),
+    // int?
+    Optional(Box<TypeName>),
     // Struct or enum
     Named(ItemPath),
+    // `Self`, the receiver type of the enclosing impl or method
+    SelfType,
+}
+
+impl TypeName {
+    /// Whether a value of this type can be stored where `other` is expected: the types
+    /// match exactly, or `other` is an optional that this type widens into.
+    pub fn is_assignable_to(&self, other: &TypeName) -> bool {
+        match other {
+            _ if self == other => true,
+            TypeName::Optional(inner) => self.is_assignable_to(inner),
+            _ => false,
+        }
+    }
 }
 
 pub struct StructDef {
@@ -115,7 +208,16 @@ pub struct TupleInit {
 
 pub struct StructInit {
     pub name: Option<Spanned<Spur>>,
-    pub fields: Vec<(Spanned<Spur>, Node<Spanned<Expr>>)>,
+    // `old` in `Person! ..old, name: "new"`; named fields override the base's
+    pub base: Option<Node<Spanned<Expr>>>,
+    pub fields: Vec<FieldInit>,
+}
+
+pub struct FieldInit {
+    pub name: Spanned<Spur>,
+    pub value: Node<Spanned<Expr>>,
+    // `name` in `Person! name`, where `value` is the binding `name` refers to
+    pub is_shorthand: bool,
 }
 
 pub enum EnumVariant {
@@ -129,6 +231,7 @@ pub struct ListInit {
 }
 
 pub struct While {
+    pub label: Option<Spanned<Spur>>,
     pub cond: Node<Spanned<Expr>>,
     pub body: Node<Spanned<Block>>,
 }
@@ -141,34 +244,59 @@ pub struct If {
 
 pub struct Let {
     pub pat: Node<Spanned<Expr>>,
+    // `let mut x`; bindings can't be reassigned or mutated through otherwise
+    pub mutable: bool,
+    // `int` in `let x: int = 1`; without it the type comes from `init`
+    pub ty: Option<Spanned<TypeName>>,
     pub init: Option<Node<Spanned<Expr>>>,
 }
 
 pub struct Loop {
+    // `'outer` in `'outer: loop`
+    pub label: Option<Spanned<Spur>>,
     pub body: Node<Spanned<Block>>,
 }
 
 pub struct For {
+    pub label: Option<Spanned<Spur>>,
     // Ident or destructuring expr
     pub item: Node<Spanned<Expr>>,
     pub iter: Node<Spanned<Expr>>,
     pub body: Node<Spanned<Block>>,
 }
 
+pub struct With {
+    pub value: Node<Spanned<Expr>>,
+    // `f` in `with open(path) as f`
+    pub binding: Option<Spanned<Spur>>,
+    // `with a() as x, b() as y` nests the second resource in the first's body, so
+    // resources are closed in reverse order
+    pub body: Node<Spanned<Block>>,
+}
+
+pub struct Break {
+    // `'outer` in `break 'outer`; the innermost loop when absent
+    pub label: Option<Spanned<Spur>>,
+    pub value: Option<Node<Spanned<Expr>>>,
+}
+
 pub struct Block {
     pub stmts: Vec<Node<Spanned<Expr>>>,
+    // The trailing expression that gives the block its value, if it has one.
+    // Not repeated in `stmts`; a block ending in `let` or an assignment has none.
+    pub tail: Option<Node<Spanned<Expr>>>,
 }
 
 pub struct NamedFunc {
     pub name: Spanned<Spur>,
-    pub args: Vec<(Spanned<Spur>, Spanned<TypeName>)>,
+    pub args: Vec<Param>,
     pub body: Node<Spanned<Block>>,
 }
 
 pub struct Method {
     pub ty: Spanned<TypeName>,
     pub name: Spanned<Spur>,
-    pub args: Vec<(Spanned<Spur>, Spanned<TypeName>)>,
+    pub args: Vec<Param>,
     pub body: Node<Spanned<Block>>,
     // Whether the method is static (has no self param)
     // Static methods are called with Type:method() instead of value.method().
@@ -176,10 +304,19 @@ pub struct Method {
 }
 
 pub struct AnonFunc {
-    pub args: Vec<(Spanned<Spur>, Spanned<TypeName>)>,
+    pub args: Vec<Param>,
     pub body: Node<Spanned<Block>>,
 }
 
+pub struct Param {
+    pub name: Spanned<Spur>,
+    // `mut x: int`
+    pub mutable: bool,
+    pub ty: Spanned<TypeName>,
+    // `"hello"` in `greeting: string = "hello"`, evaluated in the callee at call time
+    pub default: Option<Node<Spanned<Expr>>>,
+}
+
 pub struct Binary {
     pub op: Spanned<Symbol>,
     pub lhs: Node<Spanned<Expr>>,
@@ -187,13 +324,23 @@ pub struct Binary {
 }
 
 pub struct Unary {
+    // `not x` is stored as `Bang` like `!x`; only its precedence differs, binding looser
+    // than comparisons (`not a == b` is `not (a == b)`, `!a == b` is `(!a) == b`)
     pub op: Symbol,
     pub expr: Node<Spanned<Expr>>,
 }
 
 pub struct Call {
     pub func: Node<Spanned<Expr>>,
-    pub args: Vec<Spanned<Expr>>,
+    // Positional arguments come before named ones
+    pub args: Vec<Arg>,
+}
+
+pub enum Arg {
+    // `10` in `draw(10)`
+    Positional(Spanned<Expr>),
+    // `x: 10` in `draw(x: 10)`
+    Named(Spanned<Spur>, Spanned<Expr>),
 }
 
 pub struct Access {
@@ -205,3 +352,323 @@ pub struct Index {
     pub expr: Node<Spanned<Expr>>,
     pub index: Node<Spanned<Expr>>,
 }
+
+pub struct Assign {
+    // Must be an lvalue, see `Expr::is_lvalue`
+    pub target: Node<Spanned<Expr>>,
+    // `Plus` in `x += 1`, `None` for plain `=`
+    pub op: Option<Symbol>,
+    pub value: Node<Spanned<Expr>>,
+}
+
+/// Equality over AST trees that looks through `Node` handles and ignores spans.
+///
+/// Two trees parsed from differently formatted source compare equal as long as they
+/// have the same shape and contents.
+pub trait StructuralEq {
+    fn structural_eq(&self, other: &Self, nodes: &BumpMap) -> bool;
+}
+
+pub fn structural_eq(a: &Module, b: &Module, nodes: &BumpMap) -> bool {
+    a.structural_eq(b, nodes)
+}
+
+macro_rules! leaf_eq {
+    ($($ty:ty),*) => {
+        $(
+            impl StructuralEq for $ty {
+                fn structural_eq(&self, other: &Self, _: &BumpMap) -> bool {
+                    self == other
+                }
+            }
+        )*
+    };
+}
+
+leaf_eq!(Spur, i64, bool, Symbol, PathPart, TypeName);
+
+macro_rules! struct_eq {
+    ($ty:ident { $($field:ident),* }) => {
+        impl StructuralEq for $ty {
+            fn structural_eq(&self, other: &Self, nodes: &BumpMap) -> bool {
+                $(self.$field.structural_eq(&other.$field, nodes))&&*
+            }
+        }
+    };
+}
+
+impl StructuralEq for f64 {
+    fn structural_eq(&self, other: &Self, _: &BumpMap) -> bool {
+        self.to_bits() == other.to_bits()
+    }
+}
+
+impl StructuralEq for SimpleSpan {
+    fn structural_eq(&self, _: &Self, _: &BumpMap) -> bool {
+        true
+    }
+}
+
+impl<T: StructuralEq + 'static> StructuralEq for Node<T> {
+    fn structural_eq(&self, other: &Self, nodes: &BumpMap) -> bool {
+        if self == other {
+            return true;
+        }
+        match (nodes.get(*self), nodes.get(*other)) {
+            (Some(a), Some(b)) => a.structural_eq(b, nodes),
+            _ => false,
+        }
+    }
+}
+
+impl<A: StructuralEq, B: StructuralEq> StructuralEq for (A, B) {
+    fn structural_eq(&self, other: &Self, nodes: &BumpMap) -> bool {
+        self.0.structural_eq(&other.0, nodes) && self.1.structural_eq(&other.1, nodes)
+    }
+}
+
+impl<T: StructuralEq> StructuralEq for Box<T> {
+    fn structural_eq(&self, other: &Self, nodes: &BumpMap) -> bool {
+        (**self).structural_eq(other, nodes)
+    }
+}
+
+impl<T: StructuralEq> StructuralEq for Option<T> {
+    fn structural_eq(&self, other: &Self, nodes: &BumpMap) -> bool {
+        match (self, other) {
+            (Some(a), Some(b)) => a.structural_eq(b, nodes),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<T: StructuralEq> StructuralEq for Vec<T> {
+    fn structural_eq(&self, other: &Self, nodes: &BumpMap) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .zip(other)
+                .all(|(a, b)| a.structural_eq(b, nodes))
+    }
+}
+
+impl StructuralEq for Expr {
+    fn structural_eq(&self, other: &Self, nodes: &BumpMap) -> bool {
+        match (self, other) {
+            (Expr::Import(a), Expr::Import(b)) => a.structural_eq(b, nodes),
+            (Expr::Let(a), Expr::Let(b)) => a.structural_eq(b, nodes),
+            (Expr::If(a), Expr::If(b)) => a.structural_eq(b, nodes),
+            (Expr::While(a), Expr::While(b)) => a.structural_eq(b, nodes),
+            (Expr::For(a), Expr::For(b)) => a.structural_eq(b, nodes),
+            (Expr::Loop(a), Expr::Loop(b)) => a.structural_eq(b, nodes),
+            (Expr::With(a), Expr::With(b)) => a.structural_eq(b, nodes),
+            (Expr::Continue, Expr::Continue)
+            | (Expr::SelfRef, Expr::SelfRef)
+            | (Expr::Wildcard, Expr::Wildcard) => true,
+            (Expr::Break(a), Expr::Break(b)) => a.structural_eq(b, nodes),
+            (Expr::Return(a), Expr::Return(b)) => a.structural_eq(b, nodes),
+            (Expr::Paren(a), Expr::Paren(b)) => a.structural_eq(b, nodes),
+            (Expr::FuncDecl(a), Expr::FuncDecl(b)) => a.structural_eq(b, nodes),
+            (Expr::AnonFunc(a), Expr::AnonFunc(b)) => a.structural_eq(b, nodes),
+            (Expr::Method(a), Expr::Method(b)) => a.structural_eq(b, nodes),
+            (Expr::StructDef(a), Expr::StructDef(b)) => a.structural_eq(b, nodes),
+            (Expr::StructInit(a), Expr::StructInit(b)) => a.structural_eq(b, nodes),
+            (Expr::ListInit(a), Expr::ListInit(b)) => a.structural_eq(b, nodes),
+            (Expr::Ident(a), Expr::Ident(b)) => a.structural_eq(b, nodes),
+            (Expr::Int(a), Expr::Int(b)) => a.structural_eq(b, nodes),
+            (Expr::Float(a), Expr::Float(b)) => a.structural_eq(b, nodes),
+            (Expr::String(a), Expr::String(b)) => a.structural_eq(b, nodes),
+            (Expr::Bool(a), Expr::Bool(b)) => a.structural_eq(b, nodes),
+            (Expr::List(a), Expr::List(b)) => a.structural_eq(b, nodes),
+            (Expr::Binary(a), Expr::Binary(b)) => a.structural_eq(b, nodes),
+            (Expr::Unary(a), Expr::Unary(b)) => a.structural_eq(b, nodes),
+            (Expr::Call(a), Expr::Call(b)) => a.structural_eq(b, nodes),
+            (Expr::Access(a), Expr::Access(b)) => a.structural_eq(b, nodes),
+            (Expr::Index(a), Expr::Index(b)) => a.structural_eq(b, nodes),
+            (Expr::Assign(a), Expr::Assign(b)) => a.structural_eq(b, nodes),
+            _ => false,
+        }
+    }
+}
+
+impl StructuralEq for Arg {
+    fn structural_eq(&self, other: &Self, nodes: &BumpMap) -> bool {
+        match (self, other) {
+            (Arg::Positional(a), Arg::Positional(b)) => a.structural_eq(b, nodes),
+            (Arg::Named(a, a_value), Arg::Named(b, b_value)) => {
+                a.structural_eq(b, nodes) && a_value.structural_eq(b_value, nodes)
+            }
+            _ => false,
+        }
+    }
+}
+
+struct_eq!(ItemPath { items });
+struct_eq!(Import { path, alias });
+struct_eq!(StructDef { name, fields });
+struct_eq!(StructInit { name, base, fields });
+struct_eq!(FieldInit {
+    name,
+    value,
+    is_shorthand
+});
+struct_eq!(ListInit { items });
+struct_eq!(While { label, cond, body });
+struct_eq!(If { cond, body, alt });
+struct_eq!(Let {
+    pat,
+    mutable,
+    ty,
+    init
+});
+struct_eq!(Loop { label, body });
+struct_eq!(For {
+    label,
+    item,
+    iter,
+    body
+});
+struct_eq!(With {
+    value,
+    binding,
+    body
+});
+struct_eq!(Break { label, value });
+struct_eq!(Block { stmts, tail });
+struct_eq!(NamedFunc { name, args, body });
+struct_eq!(Method {
+    ty,
+    name,
+    args,
+    body,
+    is_static
+});
+struct_eq!(AnonFunc { args, body });
+struct_eq!(Param {
+    name,
+    mutable,
+    ty,
+    default
+});
+struct_eq!(Binary { op, lhs, rhs });
+struct_eq!(Unary { op, expr });
+struct_eq!(Call { func, args });
+struct_eq!(Access { expr, field });
+struct_eq!(Index { expr, index });
+struct_eq!(Assign { target, op, value });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `let x = 1 + rhs` as a module, with every span shifted by `at`
+    fn let_sum(nodes: &mut BumpMap, x: Spur, rhs: i64, at: usize) -> Module {
+        let span = |start: usize, end: usize| SimpleSpan::new(at + start, at + end);
+        let pat = nodes.insert((Expr::Ident(x), span(4, 5)));
+        let lhs = nodes.insert((Expr::Int(1), span(8, 9)));
+        let rhs = nodes.insert((Expr::Int(rhs), span(12, 13)));
+        let init = nodes.insert((
+            Expr::Binary(Binary {
+                op: (Symbol::Plus, span(10, 11)),
+                lhs,
+                rhs,
+            }),
+            span(8, 13),
+        ));
+        let stmt = nodes.insert((
+            Expr::Let(Let {
+                pat,
+                mutable: false,
+                ty: None,
+                init: Some(init),
+            }),
+            span(0, 13),
+        ));
+        nodes.insert((
+            Block {
+                stmts: vec![stmt],
+                tail: None,
+            },
+            span(0, 13),
+        ))
+    }
+
+    #[test]
+    fn structural_eq_ignores_spans() {
+        let mut rodeo = Rodeo::new();
+        let x = rodeo.get_or_intern("x");
+        let mut nodes = BumpMap::new();
+        let a = let_sum(&mut nodes, x, 2, 0);
+        let b = let_sum(&mut nodes, x, 2, 100);
+        let c = let_sum(&mut nodes, x, 3, 0);
+
+        assert!(structural_eq(&a, &b, &nodes));
+        assert!(!structural_eq(&a, &c, &nodes));
+    }
+
+    #[test]
+    fn type_names() {
+        let list = |ty| TypeName::List(Box::new(ty));
+        assert!(list(TypeName::Int) == list(TypeName::Int));
+        assert!(list(TypeName::Int) != list(TypeName::Float));
+
+        let optional_int = TypeName::Optional(Box::new(TypeName::Int));
+        assert!(TypeName::Int.is_assignable_to(&TypeName::Int));
+        assert!(TypeName::Int.is_assignable_to(&optional_int));
+        assert!(!TypeName::Int.is_assignable_to(&TypeName::String));
+        assert!(!optional_int.is_assignable_to(&TypeName::Int));
+    }
+
+    #[test]
+    fn item_paths_ignore_spans() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let mut rodeo = Rodeo::new();
+        let (std, time) = (rodeo.get_or_intern("std"), rodeo.get_or_intern("time"));
+        // `std:time` written at `at`
+        let path = |at: usize| ItemPath {
+            items: vec![
+                (PathPart::Name(std), SimpleSpan::new(at, at + 3)),
+                (PathPart::Name(time), SimpleSpan::new(at + 4, at + 8)),
+            ],
+        };
+        let hash = |path: &ItemPath| {
+            let mut hasher = DefaultHasher::new();
+            path.hash(&mut hasher);
+            hasher.finish()
+        };
+        let (a, b) = (path(7), path(40));
+        assert!(a == b);
+        assert_eq!(hash(&a), hash(&b));
+        assert_eq!(a.display(&rodeo).to_string(), "std:time");
+    }
+
+    #[test]
+    fn lvalues() {
+        let mut rodeo = Rodeo::new();
+        let [x, a, b, f] = ["x", "a", "b", "f"].map(|s| rodeo.get_or_intern(s));
+        let mut nodes = BumpMap::new();
+        let span = SimpleSpan::new(0, 1);
+        let mut node = |expr| nodes.insert((expr, span));
+
+        let access = Expr::Access(Access {
+            expr: node(Expr::Ident(a)),
+            field: (b, span),
+        });
+        let index = Expr::Index(Index {
+            expr: node(Expr::Ident(a)),
+            index: node(Expr::Int(0)),
+        });
+        let call = Expr::Call(Call {
+            func: node(Expr::Ident(f)),
+            args: Vec::new(),
+        });
+
+        assert!(Expr::Ident(x).is_lvalue());
+        assert!(access.is_lvalue());
+        assert!(index.is_lvalue());
+        assert!(!Expr::Int(1).is_lvalue());
+        assert!(!call.is_lvalue());
+    }
+}
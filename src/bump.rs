@@ -64,9 +64,63 @@ unsafe impl<T> Key for Node<T> {
     }
 }
 
+/// A node's position in a pre-order walk of its module, stable across reparses of the
+/// same source (unlike slotmap keys, which depend on allocation order).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(pub u32);
+
+/// Two-way lookup between nodes and their [`NodeId`]s.
+#[derive(Default)]
+pub struct NodeIds {
+    ids: slotmap::SecondaryMap<Node<()>, NodeId>,
+    nodes: Vec<Node<()>>,
+}
+
+impl NodeIds {
+    /// Gives `node` the next id, or returns the one it already has.
+    pub fn push(&mut self, node: KeyData) -> NodeId {
+        let node = Node::from(node);
+        if let Some(id) = self.ids.get(node) {
+            return *id;
+        }
+        let id = NodeId(self.nodes.len() as u32);
+        self.ids.insert(node, id);
+        self.nodes.push(node);
+        id
+    }
+
+    pub fn id<T>(&self, node: Node<T>) -> Option<NodeId> {
+        self.ids.get(Node::from(node.0)).copied()
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<KeyData> {
+        self.nodes.get(id.0 as usize).map(|node| node.0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+// A value in the bump, with the glue to drop it in place. bumpalo never runs
+// destructors itself, so `BumpMap` calls `drop` on whatever is still live when it's
+// dropped.
+struct Slot {
+    ptr: *mut (),
+    drop: Option<unsafe fn(*mut ())>,
+}
+
+unsafe fn drop_value<T>(ptr: *mut ()) {
+    ptr.cast::<T>().drop_in_place()
+}
+
 pub struct BumpMap {
     bump: bumpalo::Bump,
-    slots: slotmap::SlotMap<Node<()>, *mut ()>,
+    slots: slotmap::SlotMap<Node<()>, Slot>,
 }
 
 impl BumpMap {
@@ -77,18 +131,65 @@ impl BumpMap {
         }
     }
 
+    /// Reserves slots for `capacity` nodes up front.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            bump: bumpalo::Bump::new(),
+            slots: slotmap::SlotMap::with_capacity_and_key(capacity),
+        }
+    }
+
+    /// The number of live nodes.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Bytes allocated for node values so far, including those of removed nodes.
+    pub fn allocated_bytes(&self) -> usize {
+        self.bump.allocated_bytes()
+    }
+
+    /// Drops every node and frees the bump's memory for reuse, keeping its largest
+    /// chunk. Meant for throwing away a whole module's AST before reparsing it into the
+    /// same map. Nodes from before the reset no longer resolve.
+    pub fn reset(&mut self) {
+        self.drop_values();
+        self.bump.reset();
+    }
+
+    // Removing through the slotmap bumps each slot's version, which is what keeps
+    // old keys from resolving after a reset.
+    fn drop_values(&mut self) {
+        for (_, slot) in self.slots.drain() {
+            if let Some(drop) = slot.drop {
+                unsafe { drop(slot.ptr) }
+            }
+        }
+    }
+
     pub fn insert<T: 'static>(&mut self, val: T) -> Node<T> {
-        let ptr = self.bump.alloc(val);
-        let node = self
-            .slots
-            .insert(unsafe { (ptr as *mut T as *mut ()).as_mut().unwrap() });
+        let ptr = self.bump.alloc(val) as *mut T as *mut ();
+        let drop = std::mem::needs_drop::<T>().then_some(drop_value::<T> as unsafe fn(*mut ()));
+        let node = self.slots.insert(Slot { ptr, drop });
         Node(node.0, std::marker::PhantomData)
     }
 
+    /// Moves a value back out of the map, leaving the caller to drop it. Its bump memory
+    /// is not reused.
+    pub fn remove<T: 'static>(&mut self, node: Node<T>) -> Option<T> {
+        let slot = self.slots.remove(Node(node.0, PhantomData))?;
+        Some(unsafe { slot.ptr.cast::<T>().read() })
+    }
+
     pub fn get<T: 'static>(&self, node: Node<T>) -> Option<&T> {
         unsafe {
             self.slots
                 .get(Node(node.0, PhantomData))?
+                .ptr
                 .cast::<T>()
                 .as_ref()
         }
@@ -98,8 +199,89 @@ impl BumpMap {
         unsafe {
             self.slots
                 .get_mut(Node(node.0, PhantomData))?
+                .ptr
                 .cast::<T>()
                 .as_mut()
         }
     }
 }
+
+impl Drop for BumpMap {
+    fn drop(&mut self) {
+        self.drop_values();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // Counts its own drops, to check the map runs each destructor exactly once.
+    struct Counted(Rc<Cell<usize>>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn values_drop_with_the_map() {
+        let drops = Rc::new(Cell::new(0));
+        let mut map = BumpMap::new();
+        for _ in 0..3 {
+            map.insert(Counted(drops.clone()));
+        }
+        assert_eq!(drops.get(), 0);
+        drop(map);
+        assert_eq!(drops.get(), 3);
+    }
+
+    #[test]
+    fn removed_values_are_not_dropped_again() {
+        let drops = Rc::new(Cell::new(0));
+        let mut map = BumpMap::new();
+        let node = map.insert(Counted(drops.clone()));
+        map.insert(Counted(drops.clone()));
+
+        let removed = map.remove(node);
+        assert!(removed.is_some());
+        assert!(map.remove(node).is_none());
+        drop(removed);
+        assert_eq!(drops.get(), 1);
+        drop(map);
+        assert_eq!(drops.get(), 2);
+    }
+
+    #[test]
+    fn reset_drops_values_and_invalidates_nodes() {
+        let drops = Rc::new(Cell::new(0));
+        let mut map = BumpMap::new();
+        let node = map.insert(Counted(drops.clone()));
+        map.insert(Counted(drops.clone()));
+
+        map.reset();
+        assert_eq!(drops.get(), 2);
+        assert!(map.is_empty());
+        assert!(map.get(node).is_none());
+
+        map.insert(Counted(drops.clone()));
+        drop(map);
+        assert_eq!(drops.get(), 3);
+    }
+
+    #[test]
+    fn len_counts_live_nodes() {
+        let mut map = BumpMap::with_capacity(4);
+        assert!(map.is_empty());
+        let nodes = (0..10).map(|i| map.insert(i)).collect::<Vec<_>>();
+        assert_eq!(map.len(), 10);
+        assert!(map.allocated_bytes() >= 10 * std::mem::size_of::<i32>());
+
+        map.remove(nodes[3]);
+        assert_eq!(map.len(), 9);
+        assert_eq!(map.get(nodes[9]), Some(&9));
+    }
+}
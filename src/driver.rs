@@ -0,0 +1,134 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use chumsky::span::SimpleSpan;
+
+use crate::{
+    ast::{Expr, ItemPath, Module},
+    bump::BumpMap,
+};
+
+/// Modules that import each other in a loop.
+#[derive(Debug, Clone)]
+pub struct ImportCycle {
+    // Each file imports the next, and the last imports the first
+    pub files: Vec<PathBuf>,
+    // The import in the last file that closes the cycle
+    pub span: SimpleSpan,
+}
+
+/// Which files each module imports, keyed by the module's resolved path.
+#[derive(Default)]
+pub struct ImportGraph {
+    imports: BTreeMap<PathBuf, Vec<(PathBuf, SimpleSpan)>>,
+}
+
+impl ImportGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the top-level imports of the module at `path`. `resolve` maps an import
+    /// path to the file it names; imports it can't resolve are left out, since reporting
+    /// those is up to whatever loads the files.
+    pub fn add_module(
+        &mut self,
+        path: PathBuf,
+        module: &Module,
+        nodes: &BumpMap,
+        mut resolve: impl FnMut(&ItemPath) -> Option<PathBuf>,
+    ) {
+        let imports = self.imports.entry(path).or_default();
+        let Some((block, _)) = nodes.get(*module) else {
+            return;
+        };
+        for stmt in block.stmts.iter().chain(&block.tail) {
+            if let Some((Expr::Import(import), span)) = nodes.get(*stmt) {
+                if let Some(file) = resolve(&import.path) {
+                    imports.push((file, *span));
+                }
+            }
+        }
+    }
+
+    /// Finds the import cycles between the recorded modules, in path order. A file
+    /// importing itself is a cycle of one.
+    pub fn cycles(&self) -> Vec<ImportCycle> {
+        let mut cycles = Vec::new();
+        let mut done = HashSet::new();
+        for path in self.imports.keys() {
+            if !done.contains(path.as_path()) {
+                self.visit(path, &mut Vec::new(), &mut done, &mut cycles);
+            }
+        }
+        cycles
+    }
+
+    fn visit<'g>(
+        &'g self,
+        path: &'g Path,
+        stack: &mut Vec<&'g Path>,
+        done: &mut HashSet<&'g Path>,
+        cycles: &mut Vec<ImportCycle>,
+    ) {
+        stack.push(path);
+        for (import, span) in self.imports.get(path).into_iter().flatten() {
+            if let Some(start) = stack.iter().position(|p| *p == import.as_path()) {
+                cycles.push(ImportCycle {
+                    files: stack[start..].iter().map(|p| p.to_path_buf()).collect(),
+                    span: *span,
+                });
+            } else if !done.contains(import.as_path()) {
+                self.visit(import, stack, done, cycles);
+            }
+        }
+        stack.pop();
+        done.insert(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Block, Import, PathPart};
+    use lasso::{Rodeo, Spur};
+
+    // A module that only has `import <name>`
+    fn importing(nodes: &mut BumpMap, name: Spur, span: SimpleSpan) -> Module {
+        let import = Import {
+            path: ItemPath {
+                items: vec![(PathPart::Name(name), span)],
+            },
+            alias: None,
+        };
+        let stmt = nodes.insert((Expr::Import(import), span));
+        let block = Block {
+            stmts: vec![stmt],
+            tail: None,
+        };
+        nodes.insert((block, span))
+    }
+
+    #[test]
+    fn two_file_cycle() {
+        let mut rodeo = Rodeo::new();
+        let (a, b) = (rodeo.get_or_intern("a"), rodeo.get_or_intern("b"));
+        let mut nodes = BumpMap::new();
+        let a_module = importing(&mut nodes, b, SimpleSpan::new(0, 8));
+        let b_module = importing(&mut nodes, a, SimpleSpan::new(10, 18));
+
+        let resolve = |path: &ItemPath| match path.items.last() {
+            Some((PathPart::Name(name), _)) => Some(PathBuf::from(rodeo.resolve(name))),
+            _ => None,
+        };
+        let mut graph = ImportGraph::new();
+        graph.add_module(PathBuf::from("a"), &a_module, &nodes, resolve);
+        graph.add_module(PathBuf::from("b"), &b_module, &nodes, resolve);
+
+        let cycles = graph.cycles();
+        assert_eq!(cycles.len(), 1);
+        assert_eq!(cycles[0].files, [PathBuf::from("a"), PathBuf::from("b")]);
+        // The import in `b` closes the cycle
+        assert_eq!(cycles[0].span, SimpleSpan::new(10, 18));
+    }
+}
@@ -55,6 +55,10 @@ where
     lines.map(move |lines| {
         let mut nesting = vec![(Vec::new(), Vec::new(), None)];
         for (indent, (mut line, line_span)) in lines {
+            // Blank and whitespace-only lines don't open or close blocks.
+            if line.is_empty() {
+                continue;
+            }
             let mut indent = indent.as_slice();
             let mut i = 0;
             while let Some(tail) = nesting
@@ -1,9 +1,12 @@
-use chumsky::primitive::{any, none_of};
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::ops::Deref;
+
+use chumsky::primitive::{any, end, none_of};
 use chumsky::recovery::{nested_delimiters, skip_until, via_parser};
 use chumsky::recursive::recursive;
 use chumsky::span::SimpleSpan;
 use chumsky::text::{newline, Char};
-use chumsky::ParseResult;
 use chumsky::{
     extra::Full,
     input::BoxedStream,
@@ -15,6 +18,9 @@ use chumsky::{
 use lasso::{Rodeo, Spur};
 
 use crate::indent::semantic_indentation;
+use crate::numeric::{
+    format_float, parse_float, parse_hex_float, parse_int, parse_nat, NumericError,
+};
 use crate::token::Delim;
 use crate::{
     token::{Keyword, Symbol, Token},
@@ -27,7 +33,16 @@ pub trait Tokenizer<'a, O> = Parser<'a, &'a str, O, Extra<'a>> + Clone;
 pub trait Atom<'a> = Tokenizer<'a, Token>;
 
 fn ident<'a>() -> impl Atom<'a> {
-    text::unicode::ident()
+    text::unicode::ident().map_with_state(|str, _, s: &mut Rodeo| match str {
+        "_" => Token::Wildcard,
+        _ => Token::Ident(s.get_or_intern(str)),
+    })
+}
+
+// `r#type` is the identifier `type`, even though `type` alone would be a keyword.
+fn raw_ident<'a>() -> impl Atom<'a> {
+    just("r#")
+        .ignore_then(text::unicode::ident())
         .map_with_state(|str, _, s: &mut Rodeo| Token::Ident(s.get_or_intern(str)))
 }
 
@@ -50,6 +65,11 @@ fn kw<'a>() -> impl Atom<'a> {
         keyword("trait").to(Keyword::Trait),
         keyword("impl").to(Keyword::Impl),
         keyword("fn").to(Keyword::Fn),
+        keyword("self").to(Keyword::SelfValue),
+        keyword("Self").to(Keyword::SelfType),
+        keyword("not").to(Keyword::Not),
+        keyword("with").to(Keyword::With),
+        keyword("mut").to(Keyword::Mut),
     ))
     .map(Token::Keyword)
 }
@@ -59,9 +79,12 @@ pub fn sym<'a>() -> impl Atom<'a> {
         just("::").to(Symbol::DoubleColon),
         just(":").to(Symbol::Colon),
         just(",").to(Symbol::Comma),
-        just(".").to(Symbol::Dot),
+        just(";").to(Symbol::Semicolon),
         just("->").to(Symbol::Arrow),
         just("=>").to(Symbol::FatArrow),
+        // Before `&` and `|`, which would otherwise split these in two.
+        just("&&").to(Symbol::And),
+        just("||").to(Symbol::Or),
         choice((
             just("+"),
             just("-"),
@@ -107,59 +130,127 @@ pub fn sym<'a>() -> impl Atom<'a> {
                 _ => unreachable!(),
             }
         }),
+        // After the operators so that `..` and `..=` take precedence.
+        just(".").to(Symbol::Dot),
     ))
     .map(Token::Symbol)
 }
 
+fn number<'a, T>(value: Result<T, NumericError>, span: SimpleSpan) -> Result<T, Rich<'a, char>> {
+    value.map_err(|e| Rich::custom(span, e))
+}
+
+// A `u` suffix makes the literal a `Nat` instead of an `Int`. Only a lone `u` counts,
+// so `10units` is `10` followed by `units`.
+fn integer<'a>(digits: impl Tokenizer<'a, &'a str>, radix: u32) -> impl Atom<'a> {
+    let suffix = text::unicode::ident().filter(|s: &&str| *s == "u");
+    digits
+        .then(suffix.or_not())
+        // Reported rather than failed, so an overflowing `0xfff...` isn't lexed as `0`
+        // followed by the identifier `xfff...`.
+        .validate(move |(digits, suffix), span: SimpleSpan, emitter| {
+            let token = match suffix {
+                Some(_) => number(parse_nat(digits, radix), span).map(Token::Nat),
+                None => number(parse_int(digits, radix), span).map(Token::Int),
+            };
+            token.unwrap_or_else(|e| {
+                emitter.emit(e);
+                Token::Error
+            })
+        })
+}
+
 pub fn int<'a>() -> impl Atom<'a> {
-    text::int(10).from_str().unwrapped().map(Token::Int)
+    integer(text::int(10), 10)
+}
+
+// Leading zeros are fine after a prefix, as in `0x00ff`.
+fn int_radix<'a>(prefix: &'static str, radix: u32) -> impl Atom<'a> {
+    // The prefix goes inside `integer` so that errors cover the whole literal.
+    let digits = just(prefix).ignore_then(text::digits(radix).map_slice(|s: &str| s));
+    integer(digits, radix)
 }
 
 pub fn int_hex<'a>() -> impl Atom<'a> {
-    text::int(16).from_str().unwrapped().map(Token::Int)
+    int_radix("0x", 16)
 }
 
 pub fn int_bin<'a>() -> impl Atom<'a> {
-    text::int(2).from_str().unwrapped().map(Token::Int)
+    int_radix("0b", 2)
 }
 
 pub fn int_oct<'a>() -> impl Atom<'a> {
-    text::int(8).from_str().unwrapped().map(Token::Int)
+    int_radix("0o", 8)
+}
+
+// 0x1.8p3 == 1.5 * 2^3
+pub fn float_hex<'a>() -> impl Atom<'a> {
+    just("0x")
+        .ignore_then(
+            text::digits(16)
+                .then(just('.'))
+                .then(text::digits(16))
+                .then(choice((just('p'), just('P'))))
+                .then(choice((just('+'), just('-'))).or_not())
+                .then(text::digits(10))
+                .map_slice(|s: &str| s),
+        )
+        // Reported rather than failed, or `int_hex` would take the `0x1` of an
+        // overflowing `0x1.0p9999` and lex the rest as something else entirely.
+        .validate(
+            |str, span: SimpleSpan, emitter| match number(parse_hex_float(str), span) {
+                Ok(value) => Token::Float(value),
+                Err(e) => {
+                    emitter.emit(e);
+                    Token::Error
+                }
+            },
+        )
 }
 
 pub fn float<'a>() -> impl Atom<'a> {
     text::int(10)
         .then_ignore(just('.'))
-        .then(text::int(10))
+        .then(text::digits(10).collect::<String>())
         .map(|(n, dec)| format!("{}.{}", n, dec))
-        .from_str()
-        .unwrapped()
-        .map(Token::Float)
+        .try_map(|str, span| number(parse_float(&str), span).map(Token::Float))
 }
 
 pub fn float_scientific<'a>() -> impl Atom<'a> {
     text::int(10)
         .then_ignore(just('.'))
-        .then(text::int(10))
+        .then(text::digits(10).collect::<String>())
         .then_ignore(choice((just('e'), just('E'))))
         .then(choice((just('+'), just('-'))).or_not())
-        .then(text::int(10))
+        .then(text::digits(10).collect::<String>())
         .map(|(((num, decimal), sign), exp)| {
             format!("{}.{}e{}{}", num, decimal, sign.unwrap_or('+'), exp)
         })
-        .from_str()
-        .unwrapped()
-        .map(Token::Float)
+        .try_map(|str, span| number(parse_float(&str), span).map(Token::Float))
 }
 
+// Strings have no escapes, so the contents are interned straight from the source.
 pub fn string<'a>() -> impl Atom<'a> {
     none_of("\"")
         .repeated()
-        .collect::<String>()
+        .map_slice(|str: &str| str)
         .delimited_by(just('"'), just('"'))
         .map_with_state(|str, _, s: &mut Rodeo| Token::Str(s.get_or_intern(str)))
 }
 
+pub fn char<'a>() -> impl Atom<'a> {
+    none_of("'")
+        .delimited_by(just('\''), just('\''))
+        .map(Token::Char)
+}
+
+// Tried after `char`, so `'a'` is a char and only an unterminated `'name` is a label.
+pub fn label<'a>() -> impl Atom<'a> {
+    just('\'')
+        .ignore_then(text::unicode::ident())
+        .map_with_state(|str, _, s: &mut Rodeo| Token::Label(s.get_or_intern(str)))
+}
+
 pub fn bool<'a>() -> impl Atom<'a> {
     choice((keyword("true"), keyword("false"))).map_with_state(|str, _, _| match str {
         "true" => Token::Bool(true),
@@ -168,16 +259,53 @@ pub fn bool<'a>() -> impl Atom<'a> {
 }
 
 pub fn token<'a>() -> impl Atom<'a> {
-    kw().or(sym())
+    raw_ident()
+        .or(kw())
+        .or(sym())
         .or(string())
+        .or(char())
+        .or(label())
         .or(bool())
         .or(ident())
-        .or(float_scientific())
-        .or(float())
-        .or(int())
+        .or(float_hex())
         .or(int_hex())
         .or(int_bin())
         .or(int_oct())
+        .or(float_scientific())
+        .or(float())
+        .or(int())
+}
+
+// A run of characters that can't start a token, lexed as a single `Token::Error` so
+// one stray character doesn't stop the rest of the chunk from lexing. The run ends at
+// whitespace, a delimiter, or anything that does start a token.
+fn error<'a>() -> impl Atom<'a> {
+    let boundary = token().ignored().or(any()
+        .filter(|c: &char| c.is_whitespace() || "()[]{}".contains(*c))
+        .ignored());
+    // `not()` only looks ahead, so each step still has to consume its character.
+    any()
+        .and_is(boundary.not())
+        .repeated()
+        .at_least(1)
+        .validate(|_, span: SimpleSpan, emitter| {
+            emitter.emit(Rich::custom(span, "unexpected characters"));
+            Token::Error
+        })
+}
+
+// A closing delimiter with nothing open to close, lexed as an error so it doesn't take
+// the rest of the line down with it. Inside a group, `except` is the group's own closer.
+fn stray_closer<'a>(except: Option<char>) -> impl Atom<'a> {
+    any()
+        .filter(move |c: &char| ")]}".contains(*c) && Some(*c) != except)
+        .validate(|c, span: SimpleSpan, emitter| {
+            emitter.emit(Rich::custom(
+                span,
+                format!("unmatched closing delimiter `{}`", c),
+            ));
+            Token::Error
+        })
 }
 
 pub enum TokenTree {
@@ -196,12 +324,10 @@ impl Flatten for Spanned<TokenTree> {
             TokenTree::Tree(d, tts) => {
                 let mut tokens =
                     vec![(Token::Open(d), SimpleSpan::new(self.1.start, self.1.start))];
-                let mut close = self.1.end;
                 for tt in tts {
-                    close = tt.1.end;
                     tokens.extend(tt.flatten());
                 }
-                tokens.push((Token::Close(d), SimpleSpan::new(close, close)));
+                tokens.push((Token::Close(d), SimpleSpan::new(self.1.end, self.1.end)));
                 tokens
             }
         }
@@ -220,58 +346,875 @@ impl Flatten for Vec<Spanned<TokenTree>> {
 
 pub fn lexer<'a>() -> impl Tokenizer<'a, Vec<Spanned<Token>>> {
     let tt = recursive(|tt| {
-        let token_tree = tt
-            .padded()
-            .repeated()
-            .collect::<Vec<(TokenTree, SimpleSpan)>>()
-            .delimited_by(just('('), just(')'))
-            .recover_with(via_parser(
-                none_of('(').repeated().then(just(')')).map(|_| vec![]),
-            ))
-            .map(|tts| TokenTree::Tree(Delim::Paren, tts));
+        // Only parens, brackets and braces are grouped here. Blocks come from indentation
+        // and have no characters, and angles do but are left flat for the parser, since
+        // `a < b > c` is a comparison.
+        let token_tree = |delim: Delim| {
+            let (open, close) = delim.chars().expect("delimiter has no source characters");
+            just(open)
+                .ignore_then(
+                    tt.clone()
+                        .or(stray_closer(Some(close))
+                            .map_with_span(|t, span| (TokenTree::Token(t), span)))
+                        .padded()
+                        .repeated()
+                        .collect::<Vec<(TokenTree, SimpleSpan)>>(),
+                )
+                // Running out of input before the closer is reported on the opener, since
+                // that's the delimiter that needs fixing. Anything else unexpected falls
+                // through to the recovery below.
+                .then(just(close).to(true).or(end().to(false)))
+                .validate(move |(tts, closed), span: SimpleSpan, emitter| {
+                    if !closed {
+                        emitter.emit(Rich::custom(
+                            SimpleSpan::new(span.start, span.start + 1),
+                            format!("unclosed delimiter `{}`", open),
+                        ));
+                    }
+                    tts
+                })
+                .recover_with(via_parser(
+                    none_of(open).repeated().then(just(close)).map(|_| vec![]),
+                ))
+                .map(move |tts| TokenTree::Tree(delim, tts))
+        };
 
         token()
             .map(TokenTree::Token)
-            .or(token_tree)
+            .or(token_tree(Delim::Paren))
+            .or(token_tree(Delim::Bracket))
+            .or(token_tree(Delim::Brace))
+            .or(error().map(TokenTree::Token))
             .map_with_span(|tt, span| (tt, span))
     });
 
-    semantic_indentation(tt, |tts, span| (TokenTree::Tree(Delim::Block, tts), span))
-        .map(|tt| tt.flatten())
+    let line_tt = tt.or(stray_closer(None).map_with_span(|t, span| (TokenTree::Token(t), span)));
+    semantic_indentation(line_tt, |tts: Vec<Spanned<TokenTree>>, span: SimpleSpan| {
+        // `span` only covers the block's first line, so stretch it to the end of the last one.
+        let end = tts.last().map_or(span.end, |(_, last)| last.end);
+        (
+            TokenTree::Tree(Delim::Block, tts),
+            SimpleSpan::new(span.start, end),
+        )
+    })
+    .map(|tt| tt.flatten())
 }
 
+/// A foldable region of source, as byte offsets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FoldingRange {
+    pub start: usize,
+    pub end: usize,
+    pub kind: Delim,
+}
+
+/// Collects the foldable regions of a lexed chunk: every indented block, plus
+/// delimited groups that span more than one line.
+pub fn folding_ranges(tokens: &[Spanned<Token>], src: &str) -> Vec<FoldingRange> {
+    let mut open = Vec::new();
+    let mut ranges = Vec::new();
+    for (token, span) in tokens {
+        match token {
+            Token::Open(_) => open.push(span.start),
+            Token::Close(kind) => {
+                let Some(start) = open.pop() else {
+                    continue;
+                };
+                let end = span.end;
+                let multiline = src.get(start..end).is_some_and(|s| s.contains('\n'));
+                if *kind == Delim::Block || multiline {
+                    ranges.push(FoldingRange {
+                        start,
+                        end,
+                        kind: *kind,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    ranges.sort_by_key(|range| range.start);
+    ranges
+}
+
+/// Resolves every identifier in `tokens` to its name, for completion lists. With
+/// `dedup`, only the first occurrence of each name is kept.
+pub fn collect_idents(
+    tokens: &[Spanned<Token>],
+    rodeo: &Rodeo<Spur>,
+    dedup: bool,
+) -> Vec<(String, SimpleSpan)> {
+    let mut seen = HashSet::new();
+    tokens
+        .iter()
+        .filter_map(|(token, span)| match token {
+            Token::Ident(name) if !dedup || seen.insert(*name) => {
+                Some((rodeo.resolve(name).to_string(), *span))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The tokens starting on the 1-based `line` of `src`, which `tokens` were lexed
+/// from. Lines end at `\n`, `\r\n` or a lone `\r`, as in the lexer. Lines without
+/// tokens, or past the end of `src`, give an empty slice.
+pub fn tokens_on_line<'t>(
+    tokens: &'t [Spanned<Token>],
+    src: &str,
+    line: usize,
+) -> &'t [Spanned<Token>] {
+    let ends = src
+        .match_indices(['\n', '\r'])
+        .filter(|(i, end)| *end == "\n" || !src[i + 1..].starts_with('\n'));
+    let mut starts = std::iter::once(0).chain(ends.map(|(i, _)| i + 1));
+    let Some(start) = line.checked_sub(1).and_then(|n| starts.nth(n)) else {
+        return &[];
+    };
+    let end = starts.next().unwrap_or(src.len() + 1);
+    let first = tokens.partition_point(|(_, span)| span.start < start);
+    let len = tokens[first..].partition_point(|(_, span)| span.start < end);
+    &tokens[first..first + len]
+}
+
+/// How deeply groups and blocks may nest before lexing is refused.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// Finds the first point where parens and indentation blocks nest deeper than
+/// `max_depth`. The token-tree parser recurses once per group, so pathological input
+/// has to be turned away before it reaches it.
+fn check_depth(src: &str, max_depth: usize) -> Result<(), Rich<'_, char>> {
+    let too_deep = |at: usize| {
+        Err(Rich::custom(
+            SimpleSpan::new(at, at + 1),
+            "maximum nesting depth exceeded",
+        ))
+    };
+
+    let mut blocks: Vec<&str> = Vec::new();
+    // The closer each open group is waiting for. A closer of another kind is a stray
+    // error token inside the group, which stays open, so it mustn't pop anything.
+    let mut groups: Vec<char> = Vec::new();
+    let mut in_string = false;
+    let mut offset = 0;
+    // `newline()` also ends lines at `\r\n` and a lone `\r`. Splitting `\r\n` at both
+    // characters just leaves an empty line, which is skipped below.
+    for line in src.split_inclusive(['\n', '\r']) {
+        let start = offset;
+        offset += line.len();
+
+        // Indentation only opens blocks outside of groups, same as in `semantic_indentation`.
+        let body = line.trim_start_matches(|c: char| c.is_inline_whitespace());
+        if groups.is_empty() && !in_string && !body.trim().is_empty() {
+            let indent = &line[..line.len() - body.len()];
+            while blocks
+                .last()
+                .is_some_and(|outer| !indent.starts_with(outer))
+            {
+                blocks.pop();
+            }
+            if indent.len() > blocks.last().map_or(0, |outer| outer.len()) {
+                blocks.push(indent);
+                if blocks.len() > max_depth {
+                    return too_deep(start + indent.len());
+                }
+            }
+        }
+
+        let mut skip = 0;
+        for (i, c) in line.char_indices() {
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            match c {
+                '"' if in_string => in_string = false,
+                // `string()` fails on a quote that's never closed, and the lexer carries
+                // on after it, so the scan has to as well.
+                '"' => in_string = src[start + i + 1..].contains('"'),
+                _ if in_string => {}
+                // Skip char literals like `'('`, but not labels like `'outer`
+                '\'' if line[i + 1..].chars().nth(1) == Some('\'') => skip = 2,
+                '(' | '[' | '{' => {
+                    groups.push(match c {
+                        '(' => ')',
+                        '[' => ']',
+                        _ => '}',
+                    });
+                    if blocks.len() + groups.len() > max_depth {
+                        return too_deep(start + i);
+                    }
+                }
+                ')' | ']' | '}' if groups.last() == Some(&c) => {
+                    groups.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lexes source chunks into [`TokenStream`]s.
+///
+/// The lexer borrows its interner rather than owning it: the tokens it produces refer
+/// to identifiers and strings by `Spur`, so the caller needs the `Rodeo` afterwards to
+/// resolve them, and several chunks can share one.
 pub struct Lexer<'a> {
     rodeo: &'a mut Rodeo<Spur>,
+    max_depth: usize,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(rodeo: &'a mut Rodeo) -> Self {
-        Self { rodeo }
+        Self {
+            rodeo,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
     }
 
-    pub fn lex(&mut self, chunk: &'a str) -> ParseResult<Vec<Spanned<Token>>, Rich<'a, char>> {
-        lexer().parse_with_state(chunk, &mut self.rodeo)
+    /// Lexes `chunk` even if it has errors, for tools that want to keep going: bad
+    /// characters become [`Token::Error`]s, reported alongside the tokens.
+    pub fn lex_recovering(&mut self, chunk: &'a str) -> (TokenStream, Vec<Rich<'a, char>>) {
+        if let Err(e) = check_depth(chunk, self.max_depth) {
+            return (TokenStream(Vec::new()), vec![e]);
+        }
+        let (tokens, errors) = lexer()
+            .parse_with_state(chunk, &mut self.rodeo)
+            .into_output_errors();
+        (TokenStream(tokens.unwrap_or_default()), errors)
+    }
+
+    pub fn lex(&mut self, chunk: &'a str) -> Result<TokenStream, Vec<Rich<'a, char>>> {
+        check_depth(chunk, self.max_depth).map_err(|e| vec![e])?;
+        lexer()
+            .parse_with_state(chunk, &mut self.rodeo)
+            .into_result()
+            .map(TokenStream)
     }
 }
 
-pub trait PrintTokens {
-    fn print(&self, rodeo: &Rodeo<Spur>);
+/// The tokens of a chunk, in source order.
+pub struct TokenStream(Vec<Spanned<Token>>);
+
+impl TokenStream {
+    pub fn into_inner(self) -> Vec<Spanned<Token>> {
+        self.0
+    }
+
+    /// The tokens lying entirely within `span`.
+    pub fn slice(&self, span: SimpleSpan) -> &[Spanned<Token>] {
+        let start = self.0.partition_point(|(_, s)| s.start < span.start);
+        let len = self.0[start..].partition_point(|(_, s)| s.end <= span.end);
+        &self.0[start..start + len]
+    }
+
+    /// The token covering the byte at `offset`, if any. Delimiters synthesized for
+    /// blocks and groups are empty, so they're never found this way.
+    pub fn find_at(&self, offset: usize) -> Option<&Spanned<Token>> {
+        let end = self.0.partition_point(|(_, s)| s.start <= offset);
+        self.0[..end]
+            .iter()
+            .rev()
+            .find(|(_, s)| s.start < s.end)
+            .filter(|(_, s)| offset < s.end)
+    }
+
+    /// Renders one token per line, resolving interned names through `rodeo`.
+    pub fn display<'t>(&'t self, rodeo: &'t Rodeo<Spur>) -> DisplayTokens<'t> {
+        DisplayTokens {
+            tokens: self,
+            rodeo,
+            src: None,
+        }
+    }
 }
 
-impl PrintTokens for Vec<Spanned<Token>> {
-    fn print(&self, rodeo: &Rodeo<Spur>) {
-        for (token, span) in self {
+impl Deref for TokenStream {
+    type Target = [Spanned<Token>];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl IntoIterator for TokenStream {
+    type Item = Spanned<Token>;
+    type IntoIter = std::vec::IntoIter<Spanned<Token>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'t> IntoIterator for &'t TokenStream {
+    type Item = &'t Spanned<Token>;
+    type IntoIter = std::slice::Iter<'t, Spanned<Token>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+pub struct DisplayTokens<'t> {
+    tokens: &'t TokenStream,
+    rodeo: &'t Rodeo<Spur>,
+    src: Option<&'t str>,
+}
+
+impl<'t> DisplayTokens<'t> {
+    /// Adds a column with each token's source text, taken from `src` by span.
+    pub fn with_source(mut self, src: &'t str) -> Self {
+        self.src = Some(src);
+        self
+    }
+}
+
+impl Display for DisplayTokens<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rodeo = self.rodeo;
+        let lines = self.tokens.iter().map(|(token, span)| {
+            let line = match token {
+                Token::Ident(key) => format!("Ident: {} at {}", rodeo.resolve(key), span),
+                Token::Int(v) => format!("Int: {} at {}", v, span),
+                Token::Nat(v) => format!("Nat: {} at {}", v, span),
+                Token::Float(v) => format!("Float: {} at {}", format_float(*v), span),
+                Token::Str(v) => format!("Str: {} at {}", rodeo.resolve(v), span),
+                Token::Char(v) => format!("Char: {:?} at {}", v, span),
+                Token::Label(v) => format!("Label: '{} at {}", rodeo.resolve(v), span),
+                Token::Open(v) => format!("Open: {} at {}", v, span),
+                Token::Close(v) => format!("Close: {} at {}", v, span),
+                Token::Symbol(v) => format!("Symbol: {} at {}", v, span),
+                Token::Keyword(v) => format!("Keyword: {} at {}", v, span),
+                Token::Bool(v) => format!("Bool: {} at {}", v, span),
+                Token::Wildcard => format!("Wildcard at {}", span),
+                Token::Error => format!("Error at {}", span),
+            };
+            (line, span)
+        });
+
+        let Some(src) = self.src else {
+            for (line, _) in lines {
+                writeln!(f, "{}", line)?;
+            }
+            return Ok(());
+        };
+        let lines = lines.collect::<Vec<_>>();
+        let width = lines.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+        for (line, span) in lines {
+            let text = src.get(span.start..span.end).unwrap_or_default();
+            writeln!(f, "{:<width$} | {:?}", line, text, width = width)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(src: &str) -> (Vec<Token>, Rodeo) {
+        let mut rodeo = Rodeo::new();
+        let tokens = Lexer::new(&mut rodeo).lex(src).unwrap().into_inner();
+        (tokens.into_iter().map(|(token, _)| token).collect(), rodeo)
+    }
+
+    fn lex_errors(src: &str) -> Vec<SimpleSpan> {
+        let mut rodeo = Rodeo::new();
+        let errors = Lexer::new(&mut rodeo).lex(src).err().unwrap_or_default();
+        errors.iter().map(|e| *e.span()).collect()
+    }
+
+    fn lex_recovering(src: &str) -> (Vec<Token>, Vec<SimpleSpan>) {
+        let mut rodeo = Rodeo::new();
+        let (tokens, errors) = Lexer::new(&mut rodeo).lex_recovering(src);
+        (
+            tokens.into_iter().map(|(token, _)| token).collect(),
+            errors.into_iter().map(|e| *e.span()).collect(),
+        )
+    }
+
+    #[test]
+    fn deep_nesting_is_refused() {
+        let src = "(".repeat(100_000);
+        assert_eq!(
+            lex_errors(&src),
+            vec![SimpleSpan::new(DEFAULT_MAX_DEPTH, DEFAULT_MAX_DEPTH + 1)]
+        );
+    }
+
+    #[test]
+    fn mismatched_closers_do_not_hide_nesting() {
+        let src = "(]".repeat(100_000);
+        let at = 2 * DEFAULT_MAX_DEPTH;
+        assert_eq!(lex_errors(&src), vec![SimpleSpan::new(at, at + 1)]);
+    }
+
+    #[test]
+    fn unterminated_string_does_not_hide_nesting() {
+        let src = format!("\"\n{}", "(".repeat(100_000));
+        let at = 2 + DEFAULT_MAX_DEPTH;
+        assert_eq!(lex_errors(&src), vec![SimpleSpan::new(at, at + 1)]);
+    }
+
+    #[test]
+    fn parens_in_strings_are_not_nesting() {
+        let mut rodeo = Rodeo::new();
+        let src = "\"(((\"";
+        assert!(Lexer::new(&mut rodeo).with_max_depth(2).lex(src).is_ok());
+    }
+
+    #[test]
+    fn radix_literals_allow_leading_zeros() {
+        assert_eq!(lex("0x10").0, vec![Token::Int(16)]);
+        assert_eq!(lex("0x00ff").0, vec![Token::Int(255)]);
+        assert_eq!(lex("0b0011").0, vec![Token::Int(3)]);
+        assert_eq!(lex("0o007u").0, vec![Token::Nat(7)]);
+    }
+
+    #[test]
+    fn hex_floats() {
+        assert_eq!(lex("0x1.8p3").0, vec![Token::Float(12.0)]);
+        assert_eq!(lex("0x1.8p03").0, vec![Token::Float(12.0)]);
+        assert_eq!(lex("0x1.8P-1").0, vec![Token::Float(0.75)]);
+        assert_eq!(lex_errors("0x1.0p99999999999").len(), 1);
+    }
+
+    #[test]
+    fn flatten_nested_brackets() {
+        // `[1 (2)]`
+        let tree = (
+            TokenTree::Tree(
+                Delim::Bracket,
+                vec![
+                    (TokenTree::Token(Token::Int(1)), SimpleSpan::new(1, 2)),
+                    (
+                        TokenTree::Tree(
+                            Delim::Paren,
+                            vec![(TokenTree::Token(Token::Int(2)), SimpleSpan::new(4, 5))],
+                        ),
+                        SimpleSpan::new(3, 6),
+                    ),
+                ],
+            ),
+            SimpleSpan::new(0, 7),
+        );
+        assert_eq!(
+            tree.flatten(),
+            vec![
+                (Token::Open(Delim::Bracket), SimpleSpan::new(0, 0)),
+                (Token::Int(1), SimpleSpan::new(1, 2)),
+                (Token::Open(Delim::Paren), SimpleSpan::new(3, 3)),
+                (Token::Int(2), SimpleSpan::new(4, 5)),
+                (Token::Close(Delim::Paren), SimpleSpan::new(6, 6)),
+                (Token::Close(Delim::Bracket), SimpleSpan::new(7, 7)),
+            ]
+        );
+    }
+
+    #[test]
+    fn brackets() {
+        use Delim::*;
+        assert_eq!(
+            lex("[1, 2]").0,
+            vec![
+                Token::Open(Bracket),
+                Token::Int(1),
+                Token::Symbol(Symbol::Comma),
+                Token::Int(2),
+                Token::Close(Bracket),
+            ]
+        );
+        assert_eq!(
+            lex("{[()]}").0,
+            vec![
+                Token::Open(Brace),
+                Token::Open(Bracket),
+                Token::Open(Paren),
+                Token::Close(Paren),
+                Token::Close(Bracket),
+                Token::Close(Brace),
+            ]
+        );
+        assert_eq!(lex_recovering("[1").1, vec![SimpleSpan::new(0, 1)]);
+    }
+
+    #[test]
+    fn bad_characters_are_error_tokens() {
+        assert_eq!(
+            lex_recovering("$"),
+            (vec![Token::Error], vec![SimpleSpan::new(0, 1)])
+        );
+        assert_eq!(
+            lex_recovering("@@@@@"),
+            (vec![Token::Error], vec![SimpleSpan::new(0, 5)])
+        );
+    }
+
+    #[test]
+    fn stray_closer_keeps_the_line() {
+        let mut rodeo = Rodeo::new();
+        let (tokens, errors) = Lexer::new(&mut rodeo).lex_recovering("foo) bar");
+        let errors: Vec<_> = errors.into_iter().map(|e| *e.span()).collect();
+        let (foo, bar) = (rodeo.get("foo"), rodeo.get("bar"));
+        assert_eq!(
+            tokens.into_inner(),
+            vec![
+                (Token::Ident(foo.unwrap()), SimpleSpan::new(0, 3)),
+                (Token::Error, SimpleSpan::new(3, 4)),
+                (Token::Ident(bar.unwrap()), SimpleSpan::new(5, 8)),
+            ]
+        );
+        assert_eq!(errors, vec![SimpleSpan::new(3, 4)]);
+
+        let (tokens, errors) = lex_recovering("(a ] b)");
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(tokens[2], Token::Error);
+        assert_eq!(errors, vec![SimpleSpan::new(3, 4)]);
+    }
+
+    // A tree `depth` levels deep starting at `start`, with every child inside its
+    // parent's span. The shape comes from a small LCG, so it's the same every run.
+    fn gen_tree(seed: &mut u64, start: usize, depth: usize) -> Spanned<TokenTree> {
+        let mut next = || {
+            *seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (*seed >> 33) as usize
+        };
+        if depth == 0 || next() % 3 == 0 {
+            return (
+                TokenTree::Token(Token::Int(0)),
+                SimpleSpan::new(start, start + 1),
+            );
+        }
+        let delim = [Delim::Paren, Delim::Bracket, Delim::Brace, Delim::Block][next() % 4];
+        let mut children = Vec::new();
+        let mut pos = start + 1;
+        for _ in 0..next() % 4 {
+            let child = gen_tree(seed, pos, depth - 1);
+            pos = child.1.end + 1;
+            children.push(child);
+        }
+        (
+            TokenTree::Tree(delim, children),
+            SimpleSpan::new(start, pos + 1),
+        )
+    }
+
+    fn assert_well_formed(tokens: &[Spanned<Token>]) {
+        let mut depth = 0usize;
+        let mut last_start = 0;
+        for (token, span) in tokens {
             match token {
-                Token::Ident(key) => println!("Ident: {} at {}", rodeo.resolve(&key), span),
-                Token::Int(v) => println!("Int: {} at {}", v, span),
-                Token::Float(v) => println!("Float: {} at {}", v, span),
-                Token::Str(v) => println!("Str: {} at {}", rodeo.resolve(&v), span),
-                Token::Open(v) => println!("Open: {} at {}", v, span),
-                Token::Close(v) => println!("Close: {} at {}", v, span),
-                Token::Symbol(v) => println!("Symbol: {} at {}", v, span),
-                Token::Keyword(v) => println!("Keyword: {} at {}", v, span),
-                Token::Bool(v) => println!("Bool: {} at {}", v, span),
+                Token::Open(_) => depth += 1,
+                Token::Close(_) => {
+                    depth = depth.checked_sub(1).expect("close without an open");
+                }
+                _ => {}
+            }
+            assert!(span.start >= last_start, "spans out of order at {}", span);
+            last_start = span.start;
+        }
+        assert_eq!(depth, 0, "unclosed groups");
+    }
+
+    #[test]
+    fn flatten_keeps_groups_balanced_and_in_order() {
+        let mut seed = 1;
+        for _ in 0..200 {
+            let mut trees: Vec<Spanned<TokenTree>> = Vec::new();
+            for _ in 0..3 {
+                let start = trees.last().map_or(0, |(_, span)| span.end + 1);
+                trees.push(gen_tree(&mut seed, start, 6));
             }
+            assert_well_formed(&trees.flatten());
+        }
+
+        let mut rodeo = Rodeo::new();
+        let tokens = Lexer::new(&mut rodeo).lex("a (b [c {d}]) e").unwrap();
+        assert_well_formed(&tokens);
+    }
+
+    #[test]
+    fn tokens_on_each_line() {
+        let src = "let x = 1\nfoo(x)\r\n\r\nbar\rbaz";
+        let mut rodeo = Rodeo::new();
+        let tokens = Lexer::new(&mut rodeo).lex(src).unwrap();
+        let line = |n| {
+            let tokens = tokens_on_line(&tokens, src, n);
+            tokens
+                .iter()
+                .map(|(_, span)| &src[span.start..span.end])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(line(1), ["let", "x", "=", "1"]);
+        // The group's `Open` and `Close` are empty, at either end of `(x)`
+        assert_eq!(line(2), ["foo", "", "x", ""]);
+        assert!(line(3).is_empty());
+        assert_eq!(line(4), ["bar"]);
+        assert_eq!(line(5), ["baz"]);
+        assert!(line(6).is_empty());
+        assert!(line(0).is_empty());
+    }
+
+    #[test]
+    fn nat_suffix() {
+        assert_eq!(lex("10u").0, vec![Token::Nat(10)]);
+        assert_eq!(lex("10").0, vec![Token::Int(10)]);
+        let (tokens, rodeo) = lex("10units");
+        let units = rodeo.get("units").unwrap();
+        assert_eq!(tokens, vec![Token::Int(10), Token::Ident(units)]);
+    }
+
+    #[test]
+    fn integer_range() {
+        assert_eq!(
+            lex("-5").0,
+            vec![Token::Symbol(Symbol::Minus), Token::Int(5)]
+        );
+        assert_eq!(lex("9223372036854775807").0, vec![Token::Int(i64::MAX)]);
+        assert_eq!(lex("9223372036854775808u").0, vec![Token::Nat(1 << 63)]);
+        assert_eq!(
+            lex_recovering("9223372036854775808"),
+            (vec![Token::Error], vec![SimpleSpan::new(0, 19)])
+        );
+        assert_eq!(
+            lex_recovering("0x10000000000000000"),
+            (vec![Token::Error], vec![SimpleSpan::new(0, 19)])
+        );
+    }
+
+    #[test]
+    fn labels_and_chars() {
+        let (tokens, rodeo) = lex("'outer 'a'");
+        let outer = rodeo.get("outer").unwrap();
+        assert_eq!(tokens, vec![Token::Label(outer), Token::Char('a')]);
+    }
+
+    #[test]
+    fn wildcard() {
+        let (tokens, rodeo) = lex("_ _x");
+        let x = rodeo.get("_x").unwrap();
+        assert_eq!(tokens, vec![Token::Wildcard, Token::Ident(x)]);
+    }
+
+    #[test]
+    fn display_with_source() {
+        let src = "let xs = \"hi\"";
+        let mut rodeo = Rodeo::new();
+        let tokens = Lexer::new(&mut rodeo).lex(src).unwrap();
+        let shown = tokens.display(&rodeo).with_source(src).to_string();
+        let lines = shown.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("Keyword: let at "));
+        assert!(lines[0].ends_with(" | \"let\""));
+        assert!(lines[1].ends_with(" | \"xs\""));
+        assert!(lines[3].ends_with(" | \"\\\"hi\\\"\""));
+        assert!(!tokens.display(&rodeo).to_string().contains('|'));
+    }
+
+    #[test]
+    fn empty_input() {
+        assert!(lex("").0.is_empty());
+        assert!(lex("  \n\t\n   ").0.is_empty());
+    }
+
+    #[test]
+    fn raw_identifiers() {
+        let (tokens, rodeo) = lex("r#fn fn");
+        let r#fn = rodeo.get("fn").unwrap();
+        assert_eq!(
+            tokens,
+            vec![Token::Ident(r#fn), Token::Keyword(Keyword::Fn)]
+        );
+    }
+
+    #[test]
+    fn idents_for_completion() {
+        let src = "a + b(a) + c";
+        let mut rodeo = Rodeo::new();
+        let tokens = Lexer::new(&mut rodeo).lex(src).unwrap();
+        let names = |dedup| {
+            collect_idents(&tokens, &rodeo, dedup)
+                .into_iter()
+                .map(|(name, span)| (name, span.start))
+                .collect::<Vec<_>>()
+        };
+        let expected = |names: &[(&str, usize)]| {
+            names
+                .iter()
+                .map(|(name, at)| (name.to_string(), *at))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(false),
+            expected(&[("a", 0), ("b", 4), ("a", 6), ("c", 11)])
+        );
+        assert_eq!(names(true), expected(&[("a", 0), ("b", 4), ("c", 11)]));
+    }
+
+    #[test]
+    fn logical_operators() {
+        let (tokens, _) = lex("a && b || c & d");
+        let symbols = tokens
+            .into_iter()
+            .filter_map(|token| match token {
+                Token::Symbol(symbol) => Some(symbol),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(symbols, [Symbol::And, Symbol::Or, Symbol::BitAnd]);
+    }
+
+    #[test]
+    fn semicolons() {
+        let (tokens, rodeo) = lex("{ a; b }");
+        let (a, b) = (rodeo.get("a").unwrap(), rodeo.get("b").unwrap());
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Open(Delim::Brace),
+                Token::Ident(a),
+                Token::Symbol(Symbol::Semicolon),
+                Token::Ident(b),
+                Token::Close(Delim::Brace),
+            ]
+        );
+    }
+
+    fn folds(src: &str) -> Vec<(usize, usize, Delim)> {
+        let mut rodeo = Rodeo::new();
+        let tokens = Lexer::new(&mut rodeo).lex(src).unwrap();
+        folding_ranges(&tokens, src)
+            .into_iter()
+            .map(|range| (range.start, range.end, range.kind))
+            .collect()
+    }
+
+    #[test]
+    fn folding_nested_blocks() {
+        // The outer block starts at `b` and is stretched to the end of `d`, the last
+        // token in it, rather than ending with its first line.
+        let src = "if a\n    b\n    if c\n        d\ne";
+        assert_eq!(folds(src), [(9, 29, Delim::Block), (28, 29, Delim::Block)]);
+        assert_eq!(&src[9..29], "b\n    if c\n        d");
+    }
+
+    #[test]
+    fn folding_multiline_groups() {
+        // `g()` is on one line, so it doesn't fold
+        let src = "f(\n  1)\ng()";
+        assert_eq!(folds(src), [(1, 7, Delim::Paren)]);
+    }
+
+    #[test]
+    fn slice_and_find() {
+        let src = "let x = f(y)";
+        let mut rodeo = Rodeo::new();
+        let tokens = Lexer::new(&mut rodeo).lex(src).unwrap();
+        let text = |tokens: &[Spanned<Token>]| {
+            tokens
+                .iter()
+                .map(|(_, span)| &src[span.start..span.end])
+                .collect::<Vec<_>>()
+        };
+
+        // The group's empty `Open` and `Close` lie inside `f(y)` too
+        assert_eq!(
+            text(tokens.slice(SimpleSpan::new(8, 12))),
+            ["f", "", "y", ""]
+        );
+        assert_eq!(text(tokens.slice(SimpleSpan::new(3, 7))), ["x", "="]);
+        assert!(tokens.slice(SimpleSpan::new(1, 2)).is_empty());
+
+        let find = |offset| {
+            tokens
+                .find_at(offset)
+                .map(|(_, span)| &src[span.start..span.end])
+        };
+        assert_eq!(find(0), Some("let"));
+        assert_eq!(find(2), Some("let"));
+        assert_eq!(find(3), None);
+        assert_eq!(find(10), Some("y"));
+        // `(` has no token of its own, only the empty `Open`
+        assert_eq!(find(9), None);
+        assert_eq!(find(100), None);
+    }
+
+    #[test]
+    fn angles_are_not_grouped() {
+        let (tokens, rodeo) = lex("a < b > c");
+        let [a, b, c] = ["a", "b", "c"].map(|s| Token::Ident(rodeo.get(s).unwrap()));
+        assert_eq!(
+            tokens,
+            [
+                a,
+                Token::Symbol(Symbol::Lt),
+                b,
+                Token::Symbol(Symbol::Gt),
+                c
+            ]
+        );
+    }
+
+    #[test]
+    fn dots() {
+        let (tokens, rodeo) = lex("..old a.b");
+        let [old, a, b] = ["old", "a", "b"].map(|s| Token::Ident(rodeo.get(s).unwrap()));
+        assert_eq!(
+            tokens,
+            [
+                Token::Symbol(Symbol::Concat),
+                old,
+                a,
+                Token::Symbol(Symbol::Dot),
+                b
+            ]
+        );
+    }
+
+    #[test]
+    fn line_endings_lex_alike() {
+        let lf = "if a\n    b\n    c(\n      d)\ne\n";
+        let lex_spanned = |src: &str| {
+            let mut rodeo = Rodeo::new();
+            let tokens = Lexer::new(&mut rodeo).lex(src).unwrap().into_inner();
+            (tokens, rodeo)
+        };
+        let (expected, _) = lex_spanned(lf);
+        assert!(expected.contains(&(Token::Open(Delim::Block), SimpleSpan::new(9, 9))));
+
+        for newline in ["\r\n", "\r"] {
+            // Each line break before an offset moves it by the extra terminator bytes
+            let extra = newline.len() - 1;
+            let shift = |at: usize| at + extra * lf[..at].matches('\n').count();
+            let (tokens, _) = lex_spanned(&lf.replace('\n', newline));
+            let shifted = expected
+                .iter()
+                .map(|(token, span)| {
+                    (
+                        token.clone(),
+                        SimpleSpan::new(shift(span.start), shift(span.end)),
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(tokens, shifted, "{:?}", newline);
         }
     }
+
+    #[test]
+    fn display_floats_as_literals() {
+        let mut rodeo = Rodeo::new();
+        let tokens = Lexer::new(&mut rodeo).lex("1.0 2.5e300").unwrap();
+        let shown = tokens.display(&rodeo).to_string();
+        let lines = shown.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("Float: 1.0 at "), "{}", lines[0]);
+        assert!(lines[1].starts_with("Float: 2.5e300 at "), "{}", lines[1]);
+    }
 }
@@ -0,0 +1,229 @@
+use std::collections::HashSet;
+
+use chumsky::{prelude::Rich, span::SimpleSpan};
+
+use crate::{
+    ast::{Expr, Module},
+    bump::BumpMap,
+    token::{Symbol, Token},
+    visit::{Visitor, Walk},
+};
+
+fn comparison(expr: &Expr) -> Option<Symbol> {
+    match expr {
+        Expr::Binary(binary) if binary.op.0.is_comparison() => Some(binary.op.0),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct Chain {
+    operands: Vec<SimpleSpan>,
+    ops: Vec<Symbol>,
+    // The spans of the comparisons making up the chain
+    links: Vec<SimpleSpan>,
+}
+
+impl Chain {
+    fn push(&mut self, expr: &Expr, span: SimpleSpan, nodes: &BumpMap) {
+        let Expr::Binary(binary) = expr else {
+            self.operands.push(span);
+            return;
+        };
+        match (
+            comparison(expr),
+            nodes.get(binary.lhs),
+            nodes.get(binary.rhs),
+        ) {
+            (Some(op), Some((lhs, lhs_span)), Some((rhs, rhs_span))) => {
+                self.push(lhs, *lhs_span, nodes);
+                self.ops.push(op);
+                self.push(rhs, *rhs_span, nodes);
+                self.links.push(span);
+            }
+            _ => self.operands.push(span),
+        }
+    }
+}
+
+struct ChainedComparisons<'s> {
+    src: &'s str,
+    seen: HashSet<(usize, usize)>,
+    errors: Vec<Rich<'static, Token>>,
+}
+
+impl Visitor for ChainedComparisons<'_> {
+    fn expr(&mut self, expr: &Expr, span: SimpleSpan, nodes: &BumpMap) {
+        let Expr::Binary(binary) = expr else {
+            return;
+        };
+        // Parenthesized operands are `Expr::Paren`, so `(a < b) < c` is not a chain.
+        let chained = |side| {
+            nodes
+                .get(side)
+                .is_some_and(|(e, _)| comparison(e).is_some())
+        };
+        if comparison(expr).is_none()
+            || !(chained(binary.lhs) || chained(binary.rhs))
+            || self.seen.contains(&(span.start, span.end))
+        {
+            return;
+        }
+
+        let mut chain = Chain::default();
+        chain.push(expr, span, nodes);
+        self.seen
+            .extend(chain.links.iter().map(|link| (link.start, link.end)));
+
+        let text = |span: &SimpleSpan| self.src.get(span.start..span.end).unwrap_or("_");
+        let suggestion = chain
+            .ops
+            .iter()
+            .enumerate()
+            .map(|(i, op)| {
+                let lhs = text(&chain.operands[i]);
+                let rhs = text(&chain.operands[i + 1]);
+                format!("{} {} {}", lhs, op, rhs)
+            })
+            .collect::<Vec<_>>()
+            .join(" && ");
+        self.errors.push(Rich::custom(
+            span,
+            format!(
+                "comparison operators cannot be chained, did you mean `{}`?",
+                suggestion
+            ),
+        ));
+    }
+}
+
+/// Reports comparisons whose operand is itself an unparenthesized comparison, like
+/// `a < b < c`, which would compare a bool against `c`. Each chain is reported once,
+/// with a suggested rewrite into `&&`-joined comparisons.
+pub fn chained_comparisons(
+    module: &Module,
+    nodes: &BumpMap,
+    src: &str,
+) -> Vec<Rich<'static, Token>> {
+    let mut lint = ChainedComparisons {
+        src,
+        seen: HashSet::new(),
+        errors: Vec::new(),
+    };
+    module.walk(nodes, &mut lint);
+    lint.errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Binary, Block};
+    use crate::bump::Node;
+    use crate::Spanned;
+    use lasso::Rodeo;
+
+    // Builds expressions over `src` by the byte offsets of their text, as the parser would
+    struct Builder<'s> {
+        src: &'s str,
+        rodeo: Rodeo,
+        nodes: BumpMap,
+    }
+
+    impl<'s> Builder<'s> {
+        fn new(src: &'s str) -> Self {
+            Self {
+                src,
+                rodeo: Rodeo::new(),
+                nodes: BumpMap::new(),
+            }
+        }
+
+        fn ident(&mut self, name: &str) -> Node<Spanned<Expr>> {
+            let start = self.src.find(name).unwrap();
+            let name = self.rodeo.get_or_intern(name);
+            let span = SimpleSpan::new(start, start + 1);
+            self.nodes.insert((Expr::Ident(name), span))
+        }
+
+        fn binary(
+            &mut self,
+            lhs: Node<Spanned<Expr>>,
+            op: Symbol,
+            rhs: Node<Spanned<Expr>>,
+        ) -> Node<Spanned<Expr>> {
+            let lhs_span = self.nodes.get(lhs).unwrap().1;
+            let (start, end) = (lhs_span.start, self.nodes.get(rhs).unwrap().1.end);
+            let text = op.to_string();
+            let op_start = lhs_span.end + self.src[lhs_span.end..].find(&text).unwrap();
+            let op = (op, SimpleSpan::new(op_start, op_start + text.len()));
+            let binary = Expr::Binary(Binary { op, lhs, rhs });
+            self.nodes.insert((binary, SimpleSpan::new(start, end)))
+        }
+
+        fn paren(&mut self, expr: Node<Spanned<Expr>>) -> Node<Spanned<Expr>> {
+            let span = self.nodes.get(expr).unwrap().1;
+            let span = SimpleSpan::new(span.start - 1, span.end + 1);
+            self.nodes.insert((Expr::Paren(expr), span))
+        }
+
+        fn lint(mut self, stmt: Node<Spanned<Expr>>) -> Vec<String> {
+            let block = Block {
+                stmts: vec![stmt],
+                tail: None,
+            };
+            let module = self
+                .nodes
+                .insert((block, SimpleSpan::new(0, self.src.len())));
+            chained_comparisons(&module, &self.nodes, self.src)
+                .iter()
+                .map(|e| e.to_string())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn chain_is_reported_with_a_suggestion() {
+        let mut b = Builder::new("a < b < c");
+        let (x, y, z) = (b.ident("a"), b.ident("b"), b.ident("c"));
+        let lhs = b.binary(x, Symbol::Lt, y);
+        let expr = b.binary(lhs, Symbol::Lt, z);
+        let errors = b.lint(expr);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("`a < b && b < c`"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn parenthesized_comparison_is_not_a_chain() {
+        let mut b = Builder::new("(a < b) < c");
+        let (x, y, z) = (b.ident("a"), b.ident("b"), b.ident("c"));
+        let lhs = b.binary(x, Symbol::Lt, y);
+        let lhs = b.paren(lhs);
+        let expr = b.binary(lhs, Symbol::Lt, z);
+        assert!(b.lint(expr).is_empty());
+    }
+
+    #[test]
+    fn chain_is_reported_once() {
+        let mut b = Builder::new("a < b <= c");
+        let (x, y, z) = (b.ident("a"), b.ident("b"), b.ident("c"));
+        let lhs = b.binary(x, Symbol::Lt, y);
+        let expr = b.binary(lhs, Symbol::Leq, z);
+        let errors = b.lint(expr);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("`a < b && b <= c`"), "{}", errors[0]);
+
+        // The inner links of a longer chain aren't reported again
+        let mut b = Builder::new("a < b < c < d");
+        let (w, x, y, z) = (b.ident("a"), b.ident("b"), b.ident("c"), b.ident("d"));
+        let lhs = b.binary(w, Symbol::Lt, x);
+        let lhs = b.binary(lhs, Symbol::Lt, y);
+        let expr = b.binary(lhs, Symbol::Lt, z);
+        let errors = b.lint(expr);
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].contains("`a < b && b < c && c < d`"),
+            "{}",
+            errors[0]
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{token::Token, Spanned};
+
+/// Semantic token types, in the order of [`SemanticTokenType::LEGEND`]. A token's
+/// type is sent as its index into the legend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum SemanticTokenType {
+    Keyword,
+    Variable,
+    String,
+    Number,
+    Operator,
+    // Not one of the predefined LSP types, clients without a style for it ignore it
+    Label,
+}
+
+impl SemanticTokenType {
+    /// The names to register as the server's token legend.
+    pub const LEGEND: &'static [&'static str] = &[
+        "keyword", "variable", "string", "number", "operator", "label",
+    ];
+
+    fn of(token: &Token) -> Option<Self> {
+        Some(match token {
+            Token::Keyword(_) | Token::Bool(_) => SemanticTokenType::Keyword,
+            Token::Ident(_) | Token::Wildcard => SemanticTokenType::Variable,
+            Token::Str(_) | Token::Char(_) => SemanticTokenType::String,
+            Token::Int(_) | Token::Nat(_) | Token::Float(_) => SemanticTokenType::Number,
+            Token::Symbol(_) => SemanticTokenType::Operator,
+            Token::Label(_) => SemanticTokenType::Label,
+            // Groups and blocks have no text of their own.
+            Token::Open(_) | Token::Close(_) => return None,
+            // Diagnostics mark these already.
+            Token::Error => return None,
+        })
+    }
+}
+
+/// A token in LSP's relative encoding. Lines and columns count UTF-16 code units, and
+/// each token is positioned relative to the one before it: `delta_start` is from the
+/// previous token's start when both are on the same line, or from the line start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SemanticToken {
+    pub delta_line: u32,
+    pub delta_start: u32,
+    pub length: u32,
+    pub token_type: SemanticTokenType,
+}
+
+/// Encodes a token stream as LSP semantic tokens. Tokens are assumed to be in source
+/// order, as the lexer produces them. A token spanning several lines, like a multi-line
+/// string, is only highlighted on its first line.
+pub fn semantic_tokens(tokens: &[Spanned<Token>], src: &str) -> Vec<SemanticToken> {
+    let mut encoded = Vec::new();
+    // The position of `offset`, and of the last token emitted
+    let (mut offset, mut line, mut col) = (0, 0, 0);
+    let (mut prev_line, mut prev_col) = (0, 0);
+    // Lines end at `\n`, `\r\n` or a lone `\r`, as in the lexer, so a `\n` straight after
+    // a `\r` doesn't start another one.
+    let mut after_cr = false;
+    for (token, span) in tokens {
+        let Some(token_type) = SemanticTokenType::of(token) else {
+            continue;
+        };
+        if span.start >= span.end || span.start < offset {
+            continue;
+        }
+        let Some(gap) = src.get(offset..span.start) else {
+            continue;
+        };
+        for c in gap.chars() {
+            match c {
+                '\n' if after_cr => {}
+                '\r' | '\n' => {
+                    line += 1;
+                    col = 0;
+                }
+                _ => col += c.len_utf16() as u32,
+            }
+            after_cr = c == '\r';
+        }
+        offset = span.start;
+
+        let text = src.get(span.start..span.end).unwrap_or_default();
+        let first_line = text.split(['\r', '\n']).next().unwrap_or_default();
+        encoded.push(SemanticToken {
+            delta_line: line - prev_line,
+            delta_start: if line == prev_line {
+                col - prev_col
+            } else {
+                col
+            },
+            length: first_line.encode_utf16().count() as u32,
+            token_type,
+        });
+        (prev_line, prev_col) = (line, col);
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::Keyword;
+    use chumsky::span::SimpleSpan;
+    use lasso::Rodeo;
+
+    // `let x` on one line and `y` indented on the next, split by `newline`
+    fn encode(newline: &str) -> Vec<SemanticToken> {
+        let mut rodeo = Rodeo::new();
+        let (x, y) = (rodeo.get_or_intern("x"), rodeo.get_or_intern("y"));
+        let src = format!("let x{}  y", newline);
+        let y_start = 5 + newline.len() + 2;
+        let tokens = [
+            (Token::Keyword(Keyword::Let), SimpleSpan::new(0, 3)),
+            (Token::Ident(x), SimpleSpan::new(4, 5)),
+            (Token::Ident(y), SimpleSpan::new(y_start, y_start + 1)),
+        ];
+        semantic_tokens(&tokens, &src)
+    }
+
+    #[test]
+    fn delta_encoding() {
+        let token = |delta_line, delta_start, length, token_type| SemanticToken {
+            delta_line,
+            delta_start,
+            length,
+            token_type,
+        };
+        let expected = vec![
+            token(0, 0, 3, SemanticTokenType::Keyword),
+            token(0, 4, 1, SemanticTokenType::Variable),
+            token(1, 2, 1, SemanticTokenType::Variable),
+        ];
+        assert_eq!(encode("\n"), expected);
+        assert_eq!(encode("\r"), expected);
+        assert_eq!(encode("\r\n"), expected);
+    }
+}
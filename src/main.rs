@@ -38,19 +38,52 @@
 /// ```
 pub mod ast;
 pub mod bump;
+pub mod driver;
 pub mod indent;
 pub mod lexer;
+pub mod lint;
+pub mod lsp;
+pub mod numeric;
 pub mod parser;
+pub mod resolve;
 pub mod token;
+pub mod types;
+pub mod visit;
 
+/// A value paired with the source range it came from.
+///
+/// Spans are byte offsets into the lexed `&str` (chumsky's `&str` input advances by
+/// `char::len_utf8`), so `&src[span.start..span.end]` is always the token's text.
 pub type Spanned<T> = (T, SimpleSpan);
 
+/// Helpers for working with a [`Spanned`] value without destructuring the tuple.
+pub trait SpannedExt<T> {
+    /// Transforms the value, keeping its span.
+    fn map<U>(self, f: impl FnOnce(T) -> U) -> Spanned<U>;
+    fn span(&self) -> SimpleSpan;
+    fn value(&self) -> &T;
+}
+
+impl<T> SpannedExt<T> for Spanned<T> {
+    fn map<U>(self, f: impl FnOnce(T) -> U) -> Spanned<U> {
+        (f(self.0), self.1)
+    }
+
+    fn span(&self) -> SimpleSpan {
+        self.1
+    }
+
+    fn value(&self) -> &T {
+        &self.0
+    }
+}
+
 use chumsky::span::SimpleSpan;
 use lasso::Rodeo;
-use lexer::{Lexer, PrintTokens};
+use lexer::Lexer;
 
-fn main() {
-    let code = "\
+// The example from the crate docs.
+const EXAMPLE: &str = "\
 import std:time
 
 pub struct Person ::
@@ -85,7 +118,89 @@ for person in people
     person.identify()
 ";
 
+fn main() {
     let mut rodeo = Rodeo::new();
-    let tokens = Lexer::new(&mut rodeo).lex(code).unwrap();
-    tokens.print(&rodeo);
+    let tokens = Lexer::new(&mut rodeo).lex(EXAMPLE).unwrap();
+    print!("{}", tokens.display(&rodeo).with_source(EXAMPLE));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use token::Token;
+
+    #[test]
+    fn spanned_map_keeps_the_span() {
+        let int: Spanned<Token> = (Token::Int(2), SimpleSpan::new(4, 5));
+        let doubled = int.map(|token| match token {
+            Token::Int(v) => v * 2,
+            _ => unreachable!(),
+        });
+        assert_eq!(doubled, (4, SimpleSpan::new(4, 5)));
+        assert_eq!(doubled.span(), SimpleSpan::new(4, 5));
+        assert_eq!(*doubled.value(), 4);
+    }
+
+    #[test]
+    fn example_lexes() {
+        use lexer::tokens_on_line;
+        use token::{Delim, Keyword, Symbol};
+
+        let mut rodeo = Rodeo::new();
+        let tokens = Lexer::new(&mut rodeo).lex(EXAMPLE).unwrap();
+        let name = |s| Token::Ident(rodeo.get(s).unwrap());
+        let line = |n| {
+            tokens_on_line(&tokens, EXAMPLE, n)
+                .iter()
+                .map(|(token, _)| token.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            line(1),
+            [
+                Token::Keyword(Keyword::Import),
+                name("std"),
+                Token::Symbol(Symbol::Colon),
+                name("time"),
+            ]
+        );
+        // The last line is the body of the `for`, so its block closes after it
+        assert_eq!(
+            line(32),
+            [
+                Token::Open(Delim::Block),
+                name("person"),
+                Token::Symbol(Symbol::Dot),
+                name("identify"),
+                Token::Open(Delim::Paren),
+                Token::Close(Delim::Paren),
+                Token::Close(Delim::Block),
+            ]
+        );
+
+        // One block per indented body: the struct's fields, `new` and the `Person!`
+        // inside it, `age_up`, the trait, the impl and its method, and the `for`
+        let blocks = tokens
+            .iter()
+            .filter(|(token, _)| *token == Token::Open(Delim::Block))
+            .count();
+        assert_eq!(blocks, 8);
+        assert!(tokens.iter().all(|(token, _)| *token != Token::Error));
+    }
+
+    #[test]
+    fn spans_are_byte_offsets() {
+        let src = "let é = \"🦀\" + y";
+        let mut rodeo = Rodeo::new();
+        let tokens = Lexer::new(&mut rodeo).lex(src).unwrap();
+        let text = tokens
+            .iter()
+            .map(|(_, span)| &src[span.start..span.end])
+            .collect::<Vec<_>>();
+        assert_eq!(text, ["let", "é", "=", "\"🦀\"", "+", "y"]);
+        // Counting chars instead, `y` would start at 14
+        assert_eq!(tokens.last().unwrap().1, SimpleSpan::new(18, 19));
+        assert_eq!(tokens[3].0, Token::Str(rodeo.get("🦀").unwrap()));
+    }
 }
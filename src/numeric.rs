@@ -0,0 +1,193 @@
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumericError {
+    Empty,
+    InvalidDigit,
+    // The literal is well-formed but doesn't fit the target type
+    Overflow,
+}
+
+impl Display for NumericError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NumericError::Empty => write!(f, "number has no digits"),
+            NumericError::InvalidDigit => write!(f, "invalid digit in number"),
+            NumericError::Overflow => write!(f, "number is too large"),
+        }
+    }
+}
+
+fn strip_underscores(s: &str) -> Result<String, NumericError> {
+    let digits: String = s.chars().filter(|c| *c != '_').collect();
+    if digits.is_empty() {
+        return Err(NumericError::Empty);
+    }
+    Ok(digits)
+}
+
+/// Parses the digits of an integer literal (without its radix prefix). Underscores are
+/// ignored. Literals are never negative, since the lexer reads `-` as an operator.
+pub fn parse_int(s: &str, radix: u32) -> Result<i64, NumericError> {
+    let digits = strip_underscores(s)?;
+    i64::from_str_radix(&digits, radix).map_err(int_error)
+}
+
+/// Parses the digits of a natural literal like `10u`, without its radix prefix or
+/// suffix. Underscores are ignored.
+pub fn parse_nat(s: &str, radix: u32) -> Result<u64, NumericError> {
+    let digits = strip_underscores(s)?;
+    u64::from_str_radix(&digits, radix).map_err(int_error)
+}
+
+fn int_error(e: std::num::ParseIntError) -> NumericError {
+    match e.kind() {
+        std::num::IntErrorKind::PosOverflow | std::num::IntErrorKind::NegOverflow => {
+            NumericError::Overflow
+        }
+        std::num::IntErrorKind::Empty => NumericError::Empty,
+        _ => NumericError::InvalidDigit,
+    }
+}
+
+/// Parses a decimal float literal such as `1.5`, `1_000.0` or `2.5e-3`. Literals too
+/// large to represent are an error rather than infinity, and spellings Rust accepts
+/// but Luna doesn't, like `inf` and `NaN`, are invalid.
+pub fn parse_float(s: &str) -> Result<f64, NumericError> {
+    let digits = strip_underscores(s)?;
+    let unsigned = digits.strip_prefix(['+', '-']).unwrap_or(&digits);
+    if !unsigned.starts_with(|c: char| c.is_ascii_digit())
+        || !unsigned
+            .chars()
+            .all(|c| c.is_ascii_digit() || ".eE+-".contains(c))
+    {
+        return Err(NumericError::InvalidDigit);
+    }
+    let value = digits
+        .parse::<f64>()
+        .map_err(|_| NumericError::InvalidDigit)?;
+    if value.is_infinite() {
+        return Err(NumericError::Overflow);
+    }
+    Ok(value)
+}
+
+/// Parses a hexadecimal float literal without its `0x` prefix, such as `1.8p3` for
+/// 1.5 * 2^3. As with [`parse_float`], a value too large to represent is an error.
+pub fn parse_hex_float(s: &str) -> Result<f64, NumericError> {
+    let (mantissa, exp) = s.split_once(['p', 'P']).ok_or(NumericError::InvalidDigit)?;
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if int.is_empty() && frac.is_empty() {
+        return Err(NumericError::Empty);
+    }
+
+    let digit = |c: char| {
+        c.to_digit(16)
+            .map(f64::from)
+            .ok_or(NumericError::InvalidDigit)
+    };
+    let mut value = 0.0;
+    for c in int.chars() {
+        value = value * 16.0 + digit(c)?;
+    }
+    let mut scale = 1.0;
+    for c in frac.chars() {
+        scale /= 16.0;
+        value += digit(c)? * scale;
+    }
+
+    // An exponent past `i32` is still a valid literal, it just saturates the result.
+    let exp = match exp.parse::<i32>() {
+        Ok(exp) => exp,
+        Err(e) => match e.kind() {
+            std::num::IntErrorKind::PosOverflow => i32::MAX,
+            std::num::IntErrorKind::NegOverflow => i32::MIN,
+            _ => return Err(int_error(e)),
+        },
+    };
+    if value == 0.0 {
+        return Ok(0.0);
+    }
+    let value = value * 2f64.powi(exp);
+    if !value.is_finite() {
+        return Err(NumericError::Overflow);
+    }
+    Ok(value)
+}
+
+/// Formats a float as the shortest literal that parses back to the same bits, always
+/// with a fractional part so it reads back as a float: `1.0`, `-0.0`, `1.0e300`.
+/// NaN and the infinities, which have no literal form, print as `NaN`, `inf` and `-inf`.
+pub fn format_float(value: f64) -> String {
+    if value.is_nan() {
+        return "NaN".to_string();
+    }
+    // Debug is shortest-roundtrip and switches to exponent form for large and small
+    // magnitudes, but writes `1e300` rather than `1.0e300`.
+    let s = format!("{:?}", value);
+    match s.split_once('e') {
+        Some((mantissa, exp)) if !mantissa.contains('.') => format!("{}.0e{}", mantissa, exp),
+        _ => s,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn float_spellings() {
+        assert_eq!(parse_float("1_000.5"), Ok(1000.5));
+        assert_eq!(parse_float("2.5e-3"), Ok(0.0025));
+        assert_eq!(parse_float("1e400"), Err(NumericError::Overflow));
+        for s in ["NaN", "inf", "-inf", "infinity", ".5", "0x1p3"] {
+            assert_eq!(parse_float(s), Err(NumericError::InvalidDigit), "{}", s);
+        }
+    }
+
+    #[test]
+    fn floats_round_trip() {
+        for value in [0.1, 1.0, 1e300, 1e-300, -0.0, f64::MAX, f64::MIN_POSITIVE] {
+            let parsed = parse_float(&format_float(value)).unwrap();
+            assert_eq!(parsed.to_bits(), value.to_bits(), "{}", value);
+        }
+        assert_eq!(format_float(0.1), "0.1");
+        assert_eq!(format_float(-0.0), "-0.0");
+        assert_eq!(format_float(1e300), "1.0e300");
+    }
+
+    #[test]
+    fn hex_float() {
+        assert_eq!(parse_hex_float("1.8p3"), Ok(12.0));
+        assert_eq!(parse_hex_float("1.8p03"), Ok(12.0));
+        assert_eq!(parse_hex_float("0.0p99999999999"), Ok(0.0));
+        assert_eq!(parse_hex_float("1.0p-99999999999"), Ok(0.0));
+    }
+
+    #[test]
+    fn hex_float_overflow() {
+        assert_eq!(parse_hex_float("1.0p1024"), Err(NumericError::Overflow));
+        assert_eq!(
+            parse_hex_float("1.0p99999999999"),
+            Err(NumericError::Overflow)
+        );
+    }
+
+    #[test]
+    fn float_bit_patterns_round_trip() {
+        // A fixed LCG over the whole bit space, so failures reproduce
+        let mut bits = 0x2545_f491_4f6c_dd1d_u64;
+        for _ in 0..100_000 {
+            bits = bits
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let value = f64::from_bits(bits);
+            if !value.is_finite() {
+                continue;
+            }
+            let text = format_float(value);
+            let parsed = parse_float(&text).map(f64::to_bits);
+            assert_eq!(parsed, Ok(bits), "{:#x} printed as {}", bits, text);
+        }
+    }
+}
@@ -1,14 +1,23 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
 use chumsky::input::{BoxedStream, SpannedInput, Stream};
-use chumsky::primitive::{choice, just, todo};
+use chumsky::primitive::{any, choice, just, todo};
 use chumsky::recursive::recursive;
 use chumsky::select;
 use chumsky::span::SimpleSpan;
-use chumsky::{extra::Full, prelude::Rich, Parser as Parse};
-use lasso::Rodeo;
+use chumsky::{extra::Full, prelude::Rich, IterParser, Parser as Parse};
+use lasso::{Rodeo, Spur};
 
-use crate::ast::{Block, Expr, If, Module, While};
+use crate::ast::{Access, Assign, Binary, Block, Expr, If, Index, Module, StructDef, While};
 use crate::token::*;
-use crate::{bump::BumpMap, lexer::Tokens, token::Token, Spanned};
+use crate::{
+    bump::{BumpMap, Node, NodeIds},
+    lexer::Tokens,
+    token::Token,
+    visit::number_nodes,
+    Spanned,
+};
 
 #[macro_export]
 macro_rules! kw {
@@ -30,18 +39,255 @@ macro_rules! sym {
     };
 }
 
+macro_rules! well_known {
+    ($($field:ident: $name:literal),* $(,)?) => {
+        /// Names that passes compare identifiers against, interned once per
+        /// [`ParserState`] so they aren't spelled out as strings at each use.
+        pub struct WellKnown {
+            $(pub $field: Spur,)*
+        }
+
+        impl WellKnown {
+            fn new(interner: &mut Rodeo) -> Self {
+                Self {
+                    $($field: interner.get_or_intern_static($name),)*
+                }
+            }
+        }
+    };
+}
+
+// `self` and `Self` are keywords, so they never reach the interner.
+well_known! {
+    main: "main",
+    new: "new",
+    len: "len",
+    push: "push",
+    ok: "ok",
+    err: "err",
+    int: "int",
+    float: "float",
+    string: "string",
+    bool: "bool",
+}
+
+impl WellKnown {
+    /// Whether `name` is one of the built-in type names, which are in scope everywhere
+    /// without an import.
+    pub fn is_builtin_type(&self, name: Spur) -> bool {
+        [self.int, self.float, self.string, self.bool].contains(&name)
+    }
+}
+
+/// A struct field declared more than once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DuplicateField {
+    pub name: Spur,
+    pub first: SimpleSpan,
+    pub second: SimpleSpan,
+}
+
 pub struct ParserState {
     interner: Rodeo,
+    well_known: WellKnown,
     nodes: BumpMap,
+    /// Collapse binary expressions over literals into a single literal as they're built.
+    pub fold_constants: bool,
+    /// Accept `{ a; b }` wherever an indented block is expected, for one-line REPL input.
+    pub repl_mode: bool,
+    ids: NodeIds,
+    duplicate_fields: Vec<DuplicateField>,
 }
 
 impl ParserState {
     pub fn new() -> Self {
+        let mut interner = Rodeo::default();
         Self {
-            interner: Rodeo::default(),
+            well_known: WellKnown::new(&mut interner),
+            interner,
             nodes: BumpMap::new(),
+            fold_constants: false,
+            repl_mode: false,
+            ids: NodeIds::default(),
+            duplicate_fields: Vec::new(),
+        }
+    }
+
+    pub fn resolve(&self, spur: Spur) -> &str {
+        self.interner.resolve(&spur)
+    }
+
+    pub fn intern(&mut self, s: &str) -> Spur {
+        self.interner.get_or_intern(s)
+    }
+
+    pub fn well_known(&self) -> &WellKnown {
+        &self.well_known
+    }
+
+    /// Numbers the nodes of a finished module, see [`NodeId`](crate::bump::NodeId).
+    pub fn number_nodes(&mut self, module: &Module) {
+        self.ids = number_nodes(module, &self.nodes);
+    }
+
+    pub fn ids(&self) -> &NodeIds {
+        &self.ids
+    }
+
+    /// Struct fields declared twice, found by [`unique_fields`].
+    pub fn duplicate_fields(&self) -> &[DuplicateField] {
+        &self.duplicate_fields
+    }
+
+    /// Allocates a binary expression node. With `fold_constants` set, an operation on
+    /// two literals is evaluated instead and its operands are freed.
+    pub fn binary(
+        &mut self,
+        op: Spanned<Symbol>,
+        lhs: Node<Spanned<Expr>>,
+        rhs: Node<Spanned<Expr>>,
+        span: SimpleSpan,
+    ) -> Node<Spanned<Expr>> {
+        if self.fold_constants {
+            if let Some(folded) = self.fold(op.0, lhs, rhs) {
+                self.nodes.remove(lhs);
+                self.nodes.remove(rhs);
+                return self.nodes.insert((folded, span));
+            }
+        }
+        self.nodes
+            .insert((Expr::Binary(Binary { op, lhs, rhs }), span))
+    }
+
+    /// Rewrites `a += b` into `a = a + b`, giving the new `+` its own copy of the target.
+    /// Plain `=` is returned as is. An assignment whose target would run code twice once
+    /// copied, like `xs[next()] += 1`, is handed back unchanged as the error.
+    pub fn desugar_assign(&mut self, assign: Assign, span: SimpleSpan) -> Result<Assign, Assign> {
+        let Some(op) = assign.op else {
+            return Ok(assign);
+        };
+        if !self.is_simple_place(assign.target) {
+            return Err(assign);
+        }
+        let lhs = self.copy_place(assign.target);
+        // `Assign` doesn't keep the operator's span, so the new operator gets an empty one.
+        let op_span = SimpleSpan::new(span.start, span.start);
+        let value = self.binary((op, op_span), lhs, assign.value, span);
+        Ok(Assign {
+            target: assign.target,
+            op: None,
+            value,
+        })
+    }
+
+    // Places made of names, field accesses, and indexing by a name or literal, which can
+    // be evaluated twice without a difference.
+    fn is_simple_place(&self, node: Node<Spanned<Expr>>) -> bool {
+        match self.nodes.get(node) {
+            Some((Expr::Ident(_) | Expr::SelfRef, _)) => true,
+            Some((Expr::Access(access), _)) => self.is_simple_place(access.expr),
+            Some((Expr::Index(index), _)) => {
+                self.is_simple_place(index.expr)
+                    && matches!(
+                        self.nodes.get(index.index),
+                        Some((
+                            Expr::Ident(_)
+                                | Expr::Int(_)
+                                | Expr::Float(_)
+                                | Expr::String(_)
+                                | Expr::Bool(_),
+                            _
+                        ))
+                    )
+            }
+            _ => false,
         }
     }
+
+    // Copies a node accepted by `is_simple_place`, index included, into fresh nodes.
+    fn copy_place(&mut self, node: Node<Spanned<Expr>>) -> Node<Spanned<Expr>> {
+        let (expr, span) = self.nodes.get(node).expect("place was checked");
+        let span = *span;
+        let copy = match expr {
+            Expr::Access(Access { expr, field }) => {
+                let (expr, field) = (*expr, *field);
+                Expr::Access(Access {
+                    expr: self.copy_place(expr),
+                    field,
+                })
+            }
+            Expr::Index(Index { expr, index }) => {
+                let (expr, index) = (*expr, *index);
+                Expr::Index(Index {
+                    expr: self.copy_place(expr),
+                    index: self.copy_place(index),
+                })
+            }
+            Expr::Ident(name) => Expr::Ident(*name),
+            Expr::SelfRef => Expr::SelfRef,
+            Expr::Int(v) => Expr::Int(*v),
+            Expr::Float(v) => Expr::Float(*v),
+            Expr::String(v) => Expr::String(*v),
+            Expr::Bool(v) => Expr::Bool(*v),
+            _ => unreachable!("place was checked"),
+        };
+        self.nodes.insert((copy, span))
+    }
+
+    // Operations that would fail at runtime (overflow, division by zero) are left alone
+    // so the error still happens there.
+    fn fold(&self, op: Symbol, lhs: Node<Spanned<Expr>>, rhs: Node<Spanned<Expr>>) -> Option<Expr> {
+        let (lhs, _) = self.nodes.get(lhs)?;
+        let (rhs, _) = self.nodes.get(rhs)?;
+        let folded = match (lhs, rhs) {
+            (Expr::Int(a), Expr::Int(b)) => match op {
+                Symbol::Plus => Expr::Int(a.checked_add(*b)?),
+                Symbol::Minus => Expr::Int(a.checked_sub(*b)?),
+                Symbol::Times => Expr::Int(a.checked_mul(*b)?),
+                Symbol::Divide => Expr::Int(a.checked_div(*b)?),
+                Symbol::Modulo => Expr::Int(a.checked_rem(*b)?),
+                Symbol::BitAnd => Expr::Int(a & b),
+                Symbol::BitOr => Expr::Int(a | b),
+                Symbol::Xor => Expr::Int(a ^ b),
+                Symbol::LShift => Expr::Int(a.checked_shl(u32::try_from(*b).ok()?)?),
+                Symbol::RShift => Expr::Int(a.checked_shr(u32::try_from(*b).ok()?)?),
+                Symbol::Eq => Expr::Bool(a == b),
+                Symbol::Neq => Expr::Bool(a != b),
+                Symbol::Lt => Expr::Bool(a < b),
+                Symbol::Gt => Expr::Bool(a > b),
+                Symbol::Leq => Expr::Bool(a <= b),
+                Symbol::Geq => Expr::Bool(a >= b),
+                _ => return None,
+            },
+            (Expr::Float(a), Expr::Float(b)) => match op {
+                Symbol::Plus => Expr::Float(a + b),
+                Symbol::Minus => Expr::Float(a - b),
+                Symbol::Times => Expr::Float(a * b),
+                Symbol::Divide if *b != 0.0 => Expr::Float(a / b),
+                Symbol::Modulo if *b != 0.0 => Expr::Float(a % b),
+                Symbol::Eq => Expr::Bool(a == b),
+                Symbol::Neq => Expr::Bool(a != b),
+                Symbol::Lt => Expr::Bool(a < b),
+                Symbol::Gt => Expr::Bool(a > b),
+                Symbol::Leq => Expr::Bool(a <= b),
+                Symbol::Geq => Expr::Bool(a >= b),
+                _ => return None,
+            },
+            (Expr::Bool(a), Expr::Bool(b)) => match op {
+                Symbol::And => Expr::Bool(*a && *b),
+                Symbol::Or => Expr::Bool(*a || *b),
+                Symbol::Eq => Expr::Bool(a == b),
+                Symbol::Neq => Expr::Bool(a != b),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        // Floats don't fail, they overflow to infinity, which has no literal to fold into.
+        if matches!(folded, Expr::Float(v) if !v.is_finite()) {
+            return None;
+        }
+        Some(folded)
+    }
 }
 
 pub type Input<'a> = SpannedInput<Token, SimpleSpan, BoxedStream<'a, Spanned<Token>>>;
@@ -50,10 +296,75 @@ pub type Extra<'a> = Full<Rich<'a, Token>, State, ()>;
 
 pub trait Parser<'a, Output = Spanned<Block>> = chumsky::Parser<'a, Input<'a>, Output, Extra<'a>>;
 
+/// Records fields declared more than once in a struct definition, for use with
+/// `map_with_state` on the struct-def parser. The duplicates end up in
+/// [`ParserState::duplicate_fields`], with the spans of both declarations.
+pub fn unique_fields(def: StructDef, _: SimpleSpan, state: &mut ParserState) -> StructDef {
+    let mut seen = HashMap::new();
+    for ((name, span), _) in &def.fields {
+        match seen.entry(*name) {
+            Entry::Occupied(first) => state.duplicate_fields.push(DuplicateField {
+                name: *name,
+                first: *first.get(),
+                second: *span,
+            }),
+            Entry::Vacant(slot) => {
+                slot.insert(*span);
+            }
+        }
+    }
+    def
+}
+
+/// Matches a single keyword token.
+pub fn kw<'a>(keyword: Keyword) -> impl Parser<'a, Token> + Clone {
+    just(Token::Keyword(keyword))
+}
+
+/// Matches a single symbol token.
+pub fn sym<'a>(symbol: Symbol) -> impl Parser<'a, Token> + Clone {
+    just(Token::Symbol(symbol))
+}
+
+/// Matches any token of the given kind, regardless of its payload.
+pub fn kind<'a>(kind: TokenKind) -> impl Parser<'a, Token> + Clone {
+    any().filter(move |token: &Token| token.kind() == kind)
+}
+
+/// Parses the statements of a block: an indented block, or with `repl_mode` set, a
+/// braced block with `;` between statements.
+pub fn block_body<'a, O: 'a>(
+    stmt: impl Parser<'a, O> + Clone + 'a,
+) -> impl Parser<'a, Vec<O>> + Clone {
+    let indented = stmt.clone().repeated().collect().delimited_by(
+        just(Token::Open(Delim::Block)),
+        just(Token::Close(Delim::Block)),
+    );
+    let braced = stmt
+        .separated_by(sym(Symbol::Semicolon))
+        .allow_trailing()
+        .collect()
+        .delimited_by(
+            just(Token::Open(Delim::Brace)),
+            just(Token::Close(Delim::Brace)),
+        )
+        .try_map_with_state(|stmts, span, s: &mut ParserState| {
+            if s.repl_mode {
+                Ok(stmts)
+            } else {
+                Err(Rich::custom(
+                    span,
+                    "braced blocks are only allowed in the REPL",
+                ))
+            }
+        });
+    indented.or(braced)
+}
+
 // pub fn expr<'a>() -> impl Parser<'a, Spanned<Expr>> {
 //     recursive(|expr| {
 //         let r#if = recursive(|r#if| {
-//             kw!(@If)
+//             kw(Keyword::If)
 //                 .ignore_then(
 //                     expr.clone()
 //                         .map_with_state(|v, _, s: &mut ParserState| s.nodes.insert(v)),
@@ -62,14 +373,14 @@ pub trait Parser<'a, Output = Spanned<Block>> = chumsky::Parser<'a, Input<'a>, O
 //                     expr.clone()
 //                         .map_with_state(|v, _, s: &mut ParserState| s.nodes.insert(v)),
 //                 )
-//                 .then(kw!(@Else).ignore_then(expr.clone()).or_not())
+//                 .then(kw(Keyword::Else).ignore_then(expr.clone()).or_not())
 //                 .map_with_state(|(cond, body, alt), _, s: &mut ParserState| {
 //                     let node = Expr::If(If { cond, body, alt });
 //                     s.nodes.insert(node)
 //                 })
 //         });
 //
-//         let r#while = kw!(@While)
+//         let r#while = kw(Keyword::While)
 //             .ignore_then(expr.clone())
 //             .then(expr.clone())
 //             .map_with_state(|(cond, body), _, s: &mut ParserState| {
@@ -104,3 +415,267 @@ pub trait Parser<'a, Output = Spanned<Block>> = chumsky::Parser<'a, Input<'a>, O
 // pub fn module<'a>() -> impl Parser<'a, Spanned<Module>> {
 //     todo()
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Call, StructuralEq, TypeName};
+    use chumsky::input::Input;
+    use chumsky::primitive::end;
+
+    fn literal(state: &mut ParserState, expr: Expr, at: usize) -> Node<Spanned<Expr>> {
+        state.nodes.insert((expr, SimpleSpan::new(at, at + 1)))
+    }
+
+    // `1 + 2 + 3`, built the way the expression parser would
+    fn sum(state: &mut ParserState) -> Node<Spanned<Expr>> {
+        let one = literal(state, Expr::Int(1), 0);
+        let two = literal(state, Expr::Int(2), 4);
+        let plus = (Symbol::Plus, SimpleSpan::new(2, 3));
+        let lhs = state.binary(plus, one, two, SimpleSpan::new(0, 5));
+        let three = literal(state, Expr::Int(3), 8);
+        let plus = (Symbol::Plus, SimpleSpan::new(6, 7));
+        state.binary(plus, lhs, three, SimpleSpan::new(0, 9))
+    }
+
+    #[test]
+    fn folding_frees_operands() {
+        let mut state = ParserState::new();
+        state.fold_constants = true;
+        let node = sum(&mut state);
+        assert!(matches!(state.nodes.get(node), Some((Expr::Int(6), _))));
+        assert_eq!(state.nodes.len(), 1);
+
+        let mut state = ParserState::new();
+        sum(&mut state);
+        assert_eq!(state.nodes.len(), 5);
+    }
+
+    #[test]
+    fn division_by_zero_is_not_folded() {
+        let mut state = ParserState::new();
+        state.fold_constants = true;
+        let divide = (Symbol::Divide, SimpleSpan::new(4, 5));
+        for (a, b) in [
+            (Expr::Int(1), Expr::Int(0)),
+            (Expr::Float(1.0), Expr::Float(0.0)),
+        ] {
+            let (lhs, rhs) = (literal(&mut state, a, 0), literal(&mut state, b, 6));
+            let node = state.binary(divide, lhs, rhs, SimpleSpan::new(0, 7));
+            assert!(matches!(state.nodes.get(node), Some((Expr::Binary(_), _))));
+        }
+    }
+
+    #[test]
+    fn duplicate_fields_keep_both_spans() {
+        let mut state = ParserState::new();
+        let [person, name, age] = ["Person", "name", "age"].map(|s| state.intern(s));
+        let field = |name, at: usize| {
+            (
+                (name, SimpleSpan::new(at, at + 3)),
+                (TypeName::Int, SimpleSpan::new(at + 5, at + 8)),
+            )
+        };
+        let def = StructDef {
+            name: person,
+            fields: vec![field(name, 20), field(age, 40), field(name, 60)],
+        };
+        unique_fields(def, SimpleSpan::new(0, 70), &mut state);
+        assert_eq!(
+            state.duplicate_fields(),
+            [DuplicateField {
+                name,
+                first: SimpleSpan::new(20, 23),
+                second: SimpleSpan::new(60, 63),
+            }]
+        );
+    }
+
+    // Runs `parser` over the whole of `tokens`, giving each token a one-wide span
+    fn parse<'a, O>(
+        parser: impl Parser<'a, O>,
+        tokens: Vec<Token>,
+        state: &mut ParserState,
+    ) -> Option<O> {
+        let eoi = SimpleSpan::new(tokens.len(), tokens.len());
+        let tokens = tokens
+            .into_iter()
+            .enumerate()
+            .map(|(i, token)| (token, SimpleSpan::new(i, i + 1)))
+            .collect::<Vec<_>>();
+        let input = Stream::from_iter(tokens).boxed().spanned(eoi);
+        parser
+            .then_ignore(end())
+            .parse_with_state(input, state)
+            .into_result()
+            .ok()
+    }
+
+    #[test]
+    fn kind_matches_any_payload() {
+        let mut state = ParserState::new();
+        let (a, b) = (state.intern("a"), state.intern("b"));
+        let idents = kind(TokenKind::Ident).repeated().collect::<Vec<_>>();
+        let tokens = vec![Token::Ident(a), Token::Ident(b)];
+        assert_eq!(parse(idents, tokens.clone(), &mut state), Some(tokens));
+    }
+
+    #[test]
+    fn intern_and_resolve() {
+        let mut state = ParserState::new();
+        let jim = state.intern("jim");
+        assert_eq!(state.intern("jim"), jim);
+        assert_eq!(state.resolve(jim), "jim");
+        // Well-known names share the interner
+        assert_eq!(state.intern("main"), state.well_known().main);
+        let int = state.intern("int");
+        assert!(state.well_known().is_builtin_type(int));
+        assert!(!state.well_known().is_builtin_type(jim));
+    }
+
+    #[test]
+    fn compound_assign_desugars() {
+        let mut state = ParserState::new();
+        let x = state.intern("x");
+
+        // x += 1
+        let target = literal(&mut state, Expr::Ident(x), 0);
+        let one = literal(&mut state, Expr::Int(1), 5);
+        let sugared = Assign {
+            target,
+            op: Some(Symbol::Plus),
+            value: one,
+        };
+        let span = SimpleSpan::new(0, 6);
+        let desugared = state.desugar_assign(sugared, span).ok().unwrap();
+
+        // x = x + 1
+        let target = literal(&mut state, Expr::Ident(x), 0);
+        let lhs = literal(&mut state, Expr::Ident(x), 4);
+        let one = literal(&mut state, Expr::Int(1), 8);
+        let plus = (Symbol::Plus, SimpleSpan::new(6, 7));
+        let value = state.binary(plus, lhs, one, SimpleSpan::new(4, 9));
+        let expected = Assign {
+            target,
+            op: None,
+            value,
+        };
+
+        assert!(desugared.structural_eq(&expected, &state.nodes));
+    }
+
+    #[test]
+    fn side_effecting_target_is_left_alone() {
+        let mut state = ParserState::new();
+        let f = state.intern("f");
+
+        // f()[0] += 1
+        let func = literal(&mut state, Expr::Ident(f), 0);
+        let call = Expr::Call(Call {
+            func,
+            args: Vec::new(),
+        });
+        let call = state.nodes.insert((call, SimpleSpan::new(0, 3)));
+        let zero = literal(&mut state, Expr::Int(0), 4);
+        let index = Expr::Index(Index {
+            expr: call,
+            index: zero,
+        });
+        let target = state.nodes.insert((index, SimpleSpan::new(0, 6)));
+        let one = literal(&mut state, Expr::Int(1), 10);
+        let nodes_before = state.nodes.len();
+
+        let sugared = Assign {
+            target,
+            op: Some(Symbol::Plus),
+            value: one,
+        };
+        let Err(unchanged) = state.desugar_assign(sugared, SimpleSpan::new(0, 11)) else {
+            panic!("desugared a target with a call in it");
+        };
+        assert_eq!(unchanged.target, target);
+        assert_eq!(unchanged.op, Some(Symbol::Plus));
+        assert_eq!(unchanged.value, one);
+        assert_eq!(state.nodes.len(), nodes_before);
+
+        // A literal isn't a place either
+        let target = literal(&mut state, Expr::Int(2), 0);
+        let sugared = Assign {
+            target,
+            op: Some(Symbol::Plus),
+            value: one,
+        };
+        assert!(state
+            .desugar_assign(sugared, SimpleSpan::new(0, 6))
+            .is_err());
+    }
+
+    #[test]
+    fn braced_blocks_need_repl_mode() {
+        let mut state = ParserState::new();
+        let x = state.intern("x");
+        // Statements are stood in for by their tokens
+        let stmt = any()
+            .filter(|token: &Token| {
+                !matches!(token, Token::Symbol(Symbol::Semicolon) | Token::Close(_))
+            })
+            .repeated()
+            .at_least(1)
+            .collect::<Vec<_>>();
+        // { let x = 1; x }
+        let tokens = vec![
+            Token::Open(Delim::Brace),
+            Token::Keyword(Keyword::Let),
+            Token::Ident(x),
+            Token::Symbol(Symbol::Assign),
+            Token::Int(1),
+            Token::Symbol(Symbol::Semicolon),
+            Token::Ident(x),
+            Token::Close(Delim::Brace),
+        ];
+
+        assert_eq!(
+            parse(block_body(stmt.clone()), tokens.clone(), &mut state),
+            None
+        );
+
+        state.repl_mode = true;
+        assert_eq!(
+            parse(block_body(stmt), tokens.clone(), &mut state),
+            Some(vec![tokens[1..5].to_vec(), tokens[6..7].to_vec()])
+        );
+    }
+
+    #[test]
+    fn kw_matches_only_its_keyword() {
+        let mut state = ParserState::new();
+        let name = state.intern("if_");
+        let r#if = Token::Keyword(Keyword::If);
+        assert_eq!(
+            parse(kw(Keyword::If), vec![r#if.clone()], &mut state),
+            Some(r#if)
+        );
+        for other in [Token::Keyword(Keyword::Else), Token::Ident(name)] {
+            assert_eq!(parse(kw(Keyword::If), vec![other], &mut state), None);
+        }
+        assert_eq!(
+            parse(
+                sym(Symbol::Plus),
+                vec![Token::Symbol(Symbol::PlusEq)],
+                &mut state
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn float_overflow_is_not_folded() {
+        let mut state = ParserState::new();
+        state.fold_constants = true;
+        let lhs = literal(&mut state, Expr::Float(1e308), 0);
+        let rhs = literal(&mut state, Expr::Float(10.0), 8);
+        let times = (Symbol::Times, SimpleSpan::new(6, 7));
+        let node = state.binary(times, lhs, rhs, SimpleSpan::new(0, 12));
+        assert!(matches!(state.nodes.get(node), Some((Expr::Binary(_), _))));
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use chumsky::span::SimpleSpan;
+use lasso::Spur;
+
+use crate::{
+    ast::{Expr, Module, PathPart},
+    bump::{BumpMap, Node},
+    Spanned,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefKind {
+    Func,
+    Struct,
+    // `let` at the top level of a module
+    Binding,
+    // The alias, or last path segment, of an `import`
+    Import,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Def {
+    pub kind: DefKind,
+    // The span of the name itself where the AST keeps one. Struct names and import
+    // aliases are unspanned, so for those it's the whole declaring statement.
+    pub span: SimpleSpan,
+    // The declaring statement
+    pub node: Node<Spanned<Expr>>,
+}
+
+/// A name declared twice in the same scope.
+#[derive(Debug, Clone, Copy)]
+pub struct DuplicateDef {
+    pub name: Spur,
+    pub first: SimpleSpan,
+    pub second: SimpleSpan,
+}
+
+#[derive(Default)]
+pub struct SymbolTable {
+    defs: HashMap<Spur, Def>,
+    errors: Vec<DuplicateDef>,
+}
+
+impl SymbolTable {
+    pub fn get(&self, name: Spur) -> Option<&Def> {
+        self.defs.get(&name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Spur, &Def)> {
+        self.defs.iter().map(|(name, def)| (*name, def))
+    }
+
+    pub fn errors(&self) -> &[DuplicateDef] {
+        &self.errors
+    }
+
+    // A `let` may shadow an earlier `let`; any other redefinition is an error.
+    fn define(&mut self, (name, span): Spanned<Spur>, kind: DefKind, node: Node<Spanned<Expr>>) {
+        let def = Def { kind, span, node };
+        match self.defs.insert(name, def) {
+            Some(prev) if prev.kind != DefKind::Binding || kind != DefKind::Binding => {
+                self.errors.push(DuplicateDef {
+                    name,
+                    first: prev.span,
+                    second: span,
+                });
+                // Keep pointing at the first definition.
+                self.defs.insert(name, prev);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Collects the names declared at the top level of a module.
+pub fn build_symbol_table(module: &Module, nodes: &BumpMap) -> SymbolTable {
+    let mut table = SymbolTable::default();
+    let Some((block, _)) = nodes.get(*module) else {
+        return table;
+    };
+    for stmt in block.stmts.iter().chain(&block.tail) {
+        let Some((expr, span)) = nodes.get(*stmt) else {
+            continue;
+        };
+        let (name, kind) = match expr {
+            Expr::FuncDecl(func) => (func.name, DefKind::Func),
+            Expr::StructDef(def) => ((def.name, *span), DefKind::Struct),
+            // Destructuring patterns are left to the full resolver.
+            Expr::Let(binding) => match nodes.get(binding.pat) {
+                Some((Expr::Ident(name), span)) => ((*name, *span), DefKind::Binding),
+                _ => continue,
+            },
+            Expr::Import(import) => {
+                let last = import
+                    .path
+                    .items
+                    .last()
+                    .and_then(|(part, span)| match part {
+                        PathPart::Name(name) => Some((*name, *span)),
+                        _ => None,
+                    });
+                match (import.alias, last) {
+                    (Some(alias), _) => ((alias, *span), DefKind::Import),
+                    (None, Some(name)) => (name, DefKind::Import),
+                    (None, None) => continue,
+                }
+            }
+            _ => continue,
+        };
+        table.define(name, kind, *stmt);
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Block, Import, ItemPath, Let, StructDef};
+    use lasso::Rodeo;
+
+    fn span(start: usize, end: usize) -> SimpleSpan {
+        SimpleSpan::new(start, end)
+    }
+
+    fn import(nodes: &mut BumpMap, path: &[Spanned<Spur>], at: SimpleSpan) -> Node<Spanned<Expr>> {
+        let items = path
+            .iter()
+            .map(|(name, span)| (PathPart::Name(*name), *span))
+            .collect();
+        let import = Import {
+            path: ItemPath { items },
+            alias: None,
+        };
+        nodes.insert((Expr::Import(import), at))
+    }
+
+    fn struct_def(nodes: &mut BumpMap, name: Spur, at: SimpleSpan) -> Node<Spanned<Expr>> {
+        let def = StructDef {
+            name,
+            fields: Vec::new(),
+        };
+        nodes.insert((Expr::StructDef(def), at))
+    }
+
+    fn binding(
+        nodes: &mut BumpMap,
+        (name, span): Spanned<Spur>,
+        at: SimpleSpan,
+    ) -> Node<Spanned<Expr>> {
+        let pat = nodes.insert((Expr::Ident(name), span));
+        let binding = Let {
+            pat,
+            mutable: false,
+            ty: None,
+            init: None,
+        };
+        nodes.insert((Expr::Let(binding), at))
+    }
+
+    // Checks `time`, `Person` and `jim` from a module like the one in `main.rs`.
+    // `global people` isn't covered: there's no global statement in the AST yet, so
+    // no `DefKind` for it either.
+    #[test]
+    fn top_level_names() {
+        let mut rodeo = Rodeo::new();
+        let [std, time, person, jim] =
+            ["std", "time", "Person", "jim"].map(|s| rodeo.get_or_intern(s));
+        let mut nodes = BumpMap::new();
+
+        // import std:time
+        // struct Person ::
+        //     name: string
+        // let jim = ...
+        // let jim = ...
+        // struct time
+        let stmts = vec![
+            import(
+                &mut nodes,
+                &[(std, span(7, 10)), (time, span(11, 15))],
+                span(0, 15),
+            ),
+            struct_def(&mut nodes, person, span(16, 46)),
+            binding(&mut nodes, (jim, span(51, 54)), span(47, 60)),
+            binding(&mut nodes, (jim, span(65, 68)), span(61, 74)),
+            struct_def(&mut nodes, time, span(75, 86)),
+        ];
+        let module = nodes.insert((Block { stmts, tail: None }, span(0, 86)));
+        let table = build_symbol_table(&module, &nodes);
+
+        let def = |name| table.get(name).map(|def| (def.kind, def.span));
+        assert_eq!(def(time), Some((DefKind::Import, span(11, 15))));
+        assert_eq!(def(person), Some((DefKind::Struct, span(16, 46))));
+        // The second `let` shadows the first
+        assert_eq!(def(jim), Some((DefKind::Binding, span(65, 68))));
+        assert_eq!(def(std), None);
+
+        let errors = table
+            .errors()
+            .iter()
+            .map(|e| (e.name, e.first, e.second))
+            .collect::<Vec<_>>();
+        assert_eq!(errors, vec![(time, span(11, 15), span(75, 86))]);
+    }
+}
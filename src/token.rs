@@ -1,4 +1,5 @@
 use std::fmt::Display;
+use std::str::FromStr;
 
 use lasso::Spur;
 
@@ -6,13 +7,137 @@ use lasso::Spur;
 pub enum Token {
     Ident(Spur),
     Int(i64),
+    // `10u`
+    Nat(u64),
     Float(f64),
     Str(Spur),
+    Char(char),
+    // `'outer` in `'outer: loop` and `break 'outer`
+    Label(Spur),
     Open(Delim),
     Close(Delim),
     Symbol(Symbol),
     Keyword(Keyword),
     Bool(bool),
+    // `_`, but not `_foo`
+    Wildcard,
+    // A run of characters that don't lex. The lexer has already reported it, so a
+    // parser should skip these rather than report them again; there's none yet.
+    Error,
+}
+
+/// The variant of a [`Token`], without its payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    Ident,
+    Int,
+    Nat,
+    Float,
+    Str,
+    Char,
+    Label,
+    Open,
+    Close,
+    Symbol,
+    Keyword,
+    Bool,
+    Wildcard,
+    Error,
+}
+
+impl Token {
+    pub fn kind(&self) -> TokenKind {
+        match self {
+            Token::Ident(_) => TokenKind::Ident,
+            Token::Int(_) => TokenKind::Int,
+            Token::Nat(_) => TokenKind::Nat,
+            Token::Float(_) => TokenKind::Float,
+            Token::Str(_) => TokenKind::Str,
+            Token::Char(_) => TokenKind::Char,
+            Token::Label(_) => TokenKind::Label,
+            Token::Open(_) => TokenKind::Open,
+            Token::Close(_) => TokenKind::Close,
+            Token::Symbol(_) => TokenKind::Symbol,
+            Token::Keyword(_) => TokenKind::Keyword,
+            Token::Bool(_) => TokenKind::Bool,
+            Token::Wildcard => TokenKind::Wildcard,
+            Token::Error => TokenKind::Error,
+        }
+    }
+}
+
+/// The TextMate scope a highlighter should use for a token.
+pub fn highlight_scope(token: &Token) -> &'static str {
+    match token {
+        Token::Ident(_) => "variable.other",
+        Token::Wildcard => "variable.language.wildcard",
+        Token::Error => "invalid.illegal",
+        Token::Int(_) | Token::Nat(_) | Token::Float(_) => "constant.numeric",
+        Token::Bool(_) => "constant.language.boolean",
+        Token::Str(_) => "string.quoted.double",
+        Token::Char(_) => "string.quoted.single",
+        Token::Label(_) => "entity.name.label",
+        Token::Open(delim) | Token::Close(delim) => match delim {
+            Delim::Paren => "punctuation.section.parens",
+            Delim::Bracket => "punctuation.section.brackets",
+            Delim::Brace => "punctuation.section.braces",
+            Delim::Angle => "punctuation.definition.generic",
+            Delim::Block => "meta.block",
+        },
+        Token::Keyword(keyword) => match keyword {
+            Keyword::If
+            | Keyword::Else
+            | Keyword::For
+            | Keyword::In
+            | Keyword::While
+            | Keyword::Loop
+            | Keyword::Break
+            | Keyword::Continue
+            | Keyword::Return
+            | Keyword::With => "keyword.control",
+            Keyword::Import => "keyword.control.import",
+            Keyword::Fn | Keyword::Struct | Keyword::Trait | Keyword::Impl => "storage.type",
+            Keyword::Let | Keyword::Global => "storage.type.binding",
+            Keyword::Pub | Keyword::Mut => "storage.modifier",
+            Keyword::SelfValue => "variable.language.self",
+            Keyword::SelfType => "support.type.self",
+            Keyword::Not => "keyword.operator.logical",
+        },
+        Token::Symbol(symbol) => match symbol {
+            Symbol::Plus
+            | Symbol::Minus
+            | Symbol::Times
+            | Symbol::Divide
+            | Symbol::Modulo
+            | Symbol::Concat => "keyword.operator.arithmetic",
+            Symbol::BitAnd | Symbol::BitOr | Symbol::Xor | Symbol::LShift | Symbol::RShift => {
+                "keyword.operator.bitwise"
+            }
+            Symbol::And | Symbol::Or | Symbol::Bang => "keyword.operator.logical",
+            Symbol::Eq | Symbol::Neq | Symbol::Lt | Symbol::Gt | Symbol::Leq | Symbol::Geq => {
+                "keyword.operator.comparison"
+            }
+            Symbol::Assign
+            | Symbol::PlusEq
+            | Symbol::MinusEq
+            | Symbol::TimesEq
+            | Symbol::DivideEq
+            | Symbol::ModuloEq
+            | Symbol::ConcatEq
+            | Symbol::BitAndEq
+            | Symbol::BitOrEq
+            | Symbol::XorEq
+            | Symbol::LShiftEq
+            | Symbol::RShiftEq
+            | Symbol::InitAssign => "keyword.operator.assignment",
+            Symbol::Optional => "keyword.operator.optional",
+            Symbol::Dot | Symbol::DoubleColon => "punctuation.accessor",
+            Symbol::Colon => "punctuation.separator.colon",
+            Symbol::Comma => "punctuation.separator.comma",
+            Symbol::Semicolon => "punctuation.terminator",
+            Symbol::Arrow | Symbol::FatArrow => "punctuation.separator.arrow",
+        },
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -21,6 +146,7 @@ pub enum Symbol {
     DoubleColon,
     Dot,
     Comma,
+    Semicolon,
     Arrow,
     FatArrow,
     Plus,
@@ -66,6 +192,7 @@ impl Display for Symbol {
             Symbol::DoubleColon => write!(f, "::"),
             Symbol::Dot => write!(f, "."),
             Symbol::Comma => write!(f, ","),
+            Symbol::Semicolon => write!(f, ";"),
             Symbol::Arrow => write!(f, "->"),
             Symbol::FatArrow => write!(f, "=>"),
             Symbol::Plus => write!(f, "+"),
@@ -106,12 +233,101 @@ impl Display for Symbol {
     }
 }
 
+impl Symbol {
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Symbol::Eq | Symbol::Neq | Symbol::Lt | Symbol::Gt | Symbol::Leq | Symbol::Geq
+        )
+    }
+
+    /// Left and right binding powers of an infix operator, for precedence climbing.
+    /// Tighter operators bind higher, and every level is left-associative:
+    /// `* / %` > `+ -` > shifts > `..` > `&` > `^` > `|` > comparisons > `&&` > `||`.
+    pub fn binding_power(&self) -> Option<(u8, u8)> {
+        let power = match self {
+            Symbol::Or => 1,
+            Symbol::And => 3,
+            Symbol::Eq | Symbol::Neq | Symbol::Lt | Symbol::Gt | Symbol::Leq | Symbol::Geq => 5,
+            Symbol::BitOr => 7,
+            Symbol::Xor => 9,
+            Symbol::BitAnd => 11,
+            Symbol::Concat => 13,
+            Symbol::LShift | Symbol::RShift => 15,
+            Symbol::Plus | Symbol::Minus => 17,
+            Symbol::Times | Symbol::Divide | Symbol::Modulo => 19,
+            _ => return None,
+        };
+        Some((power, power + 1))
+    }
+
+    /// Splits an assignment operator into the binary operator it applies:
+    /// `=` gives `Some(None)`, `+=` gives `Some(Some(Plus))`, anything else `None`.
+    pub fn assign_op(&self) -> Option<Option<Symbol>> {
+        let op = match self {
+            Symbol::Assign => return Some(None),
+            Symbol::PlusEq => Symbol::Plus,
+            Symbol::MinusEq => Symbol::Minus,
+            Symbol::TimesEq => Symbol::Times,
+            Symbol::DivideEq => Symbol::Divide,
+            Symbol::ModuloEq => Symbol::Modulo,
+            Symbol::ConcatEq => Symbol::Concat,
+            Symbol::BitAndEq => Symbol::BitAnd,
+            Symbol::BitOrEq => Symbol::BitOr,
+            Symbol::XorEq => Symbol::Xor,
+            Symbol::LShiftEq => Symbol::LShift,
+            Symbol::RShiftEq => Symbol::RShift,
+            _ => return None,
+        };
+        Some(Some(op))
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Delim {
     Paren,
     Bracket,
     Brace,
     Block,
+    // `<...>` around generic arguments. The lexer never groups angles, since `a < b > c`
+    // is a comparison; the parser balances them itself in type position.
+    Angle,
+}
+
+impl Delim {
+    /// The text opening this delimiter in reconstructed source. Blocks open with a
+    /// newline; the printer indents their contents.
+    pub fn open_str(&self) -> &'static str {
+        match self {
+            Delim::Paren => "(",
+            Delim::Bracket => "[",
+            Delim::Brace => "{",
+            Delim::Angle => "<",
+            Delim::Block => "\n",
+        }
+    }
+
+    /// The text closing this delimiter. Blocks close by dedenting, so there's none.
+    pub fn close_str(&self) -> &'static str {
+        match self {
+            Delim::Paren => ")",
+            Delim::Bracket => "]",
+            Delim::Brace => "}",
+            Delim::Angle => ">",
+            Delim::Block => "",
+        }
+    }
+
+    /// The characters opening and closing this delimiter in source, if it has any.
+    pub fn chars(&self) -> Option<(char, char)> {
+        match self {
+            Delim::Paren => Some(('(', ')')),
+            Delim::Bracket => Some(('[', ']')),
+            Delim::Brace => Some(('{', '}')),
+            Delim::Angle => Some(('<', '>')),
+            Delim::Block => None,
+        }
+    }
 }
 
 impl Display for Delim {
@@ -121,6 +337,7 @@ impl Display for Delim {
             Delim::Bracket => write!(f, "Bracket"),
             Delim::Brace => write!(f, "Brace"),
             Delim::Block => write!(f, "Block"),
+            Delim::Angle => write!(f, "Angle"),
         }
     }
 }
@@ -144,6 +361,11 @@ pub enum Keyword {
     Return,
     Global,
     Let,
+    SelfValue,
+    SelfType,
+    Not,
+    With,
+    Mut,
 }
 
 impl Display for Keyword {
@@ -166,6 +388,239 @@ impl Display for Keyword {
             Keyword::Return => write!(f, "return"),
             Keyword::Global => write!(f, "global"),
             Keyword::Let => write!(f, "let"),
+            Keyword::SelfValue => write!(f, "self"),
+            Keyword::SelfType => write!(f, "Self"),
+            Keyword::Not => write!(f, "not"),
+            Keyword::With => write!(f, "with"),
+            Keyword::Mut => write!(f, "mut"),
+        }
+    }
+}
+
+/// Returned when a string isn't the spelling of any keyword.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseKeywordError;
+
+impl Display for ParseKeywordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "not a keyword")
+    }
+}
+
+impl FromStr for Keyword {
+    type Err = ParseKeywordError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "fn" => Keyword::Fn,
+            "pub" => Keyword::Pub,
+            "import" => Keyword::Import,
+            "struct" => Keyword::Struct,
+            "trait" => Keyword::Trait,
+            "impl" => Keyword::Impl,
+            "for" => Keyword::For,
+            "in" => Keyword::In,
+            "if" => Keyword::If,
+            "else" => Keyword::Else,
+            "while" => Keyword::While,
+            "loop" => Keyword::Loop,
+            "break" => Keyword::Break,
+            "continue" => Keyword::Continue,
+            "return" => Keyword::Return,
+            "global" => Keyword::Global,
+            "let" => Keyword::Let,
+            "self" => Keyword::SelfValue,
+            "Self" => Keyword::SelfType,
+            "not" => Keyword::Not,
+            "with" => Keyword::With,
+            "mut" => Keyword::Mut,
+            _ => return Err(ParseKeywordError),
+        })
+    }
+}
+
+/// Returned when a string isn't the spelling of any symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseSymbolError;
+
+impl Display for ParseSymbolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "not a symbol")
+    }
+}
+
+impl FromStr for Symbol {
+    type Err = ParseSymbolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            ":" => Symbol::Colon,
+            "::" => Symbol::DoubleColon,
+            "." => Symbol::Dot,
+            "," => Symbol::Comma,
+            ";" => Symbol::Semicolon,
+            "->" => Symbol::Arrow,
+            "=>" => Symbol::FatArrow,
+            "+" => Symbol::Plus,
+            "-" => Symbol::Minus,
+            "*" => Symbol::Times,
+            "/" => Symbol::Divide,
+            "%" => Symbol::Modulo,
+            "=" => Symbol::Assign,
+            "?" => Symbol::Optional,
+            "!" => Symbol::Bang,
+            ".." => Symbol::Concat,
+            "&&" => Symbol::And,
+            "||" => Symbol::Or,
+            "&" => Symbol::BitAnd,
+            "|" => Symbol::BitOr,
+            "^" => Symbol::Xor,
+            "<<" => Symbol::LShift,
+            ">>" => Symbol::RShift,
+            "==" => Symbol::Eq,
+            "!=" => Symbol::Neq,
+            "<" => Symbol::Lt,
+            ">" => Symbol::Gt,
+            "<=" => Symbol::Leq,
+            ">=" => Symbol::Geq,
+            "+=" => Symbol::PlusEq,
+            "-=" => Symbol::MinusEq,
+            "*=" => Symbol::TimesEq,
+            "/=" => Symbol::DivideEq,
+            "%=" => Symbol::ModuloEq,
+            "..=" => Symbol::ConcatEq,
+            "&=" => Symbol::BitAndEq,
+            "|=" => Symbol::BitOrEq,
+            "^=" => Symbol::XorEq,
+            "<<=" => Symbol::LShiftEq,
+            ">>=" => Symbol::RShiftEq,
+            "?=" => Symbol::InitAssign,
+            _ => return Err(ParseSymbolError),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbols_round_trip() {
+        use Symbol::*;
+        let symbols = [
+            Colon,
+            DoubleColon,
+            Dot,
+            Comma,
+            Semicolon,
+            Arrow,
+            FatArrow,
+            Plus,
+            Minus,
+            Times,
+            Divide,
+            Modulo,
+            Assign,
+            Optional,
+            Bang,
+            Concat,
+            And,
+            Or,
+            BitAnd,
+            BitOr,
+            Xor,
+            LShift,
+            RShift,
+            Eq,
+            Neq,
+            Lt,
+            Gt,
+            Leq,
+            Geq,
+            PlusEq,
+            MinusEq,
+            TimesEq,
+            DivideEq,
+            ModuloEq,
+            ConcatEq,
+            BitAndEq,
+            BitOrEq,
+            XorEq,
+            LShiftEq,
+            RShiftEq,
+            InitAssign,
+        ];
+        for symbol in symbols {
+            assert_eq!(symbol.to_string().parse(), Ok(symbol));
+        }
+        assert_eq!("=>=".parse::<Symbol>(), Err(ParseSymbolError));
+    }
+
+    #[test]
+    fn keywords_round_trip() {
+        use Keyword::*;
+        let keywords = [
+            Fn, Pub, Import, Struct, Trait, Impl, For, In, If, Else, While, Loop, Break, Continue,
+            Return, Global, Let, SelfValue, SelfType, Not, With, Mut,
+        ];
+        for keyword in keywords {
+            assert_eq!(keyword.to_string().parse(), Ok(keyword));
+        }
+        assert_eq!("true".parse::<Keyword>(), Err(ParseKeywordError));
+    }
+
+    #[test]
+    fn highlight_scopes() {
+        let scopes = [
+            (Token::Keyword(Keyword::While), "keyword.control"),
+            (Token::Keyword(Keyword::Let), "storage.type.binding"),
+            (Token::Symbol(Symbol::PlusEq), "keyword.operator.assignment"),
+            (Token::Symbol(Symbol::Leq), "keyword.operator.comparison"),
+            (Token::Open(Delim::Bracket), "punctuation.section.brackets"),
+            (Token::Close(Delim::Block), "meta.block"),
+            (Token::Nat(1), "constant.numeric"),
+            (Token::Char('a'), "string.quoted.single"),
+            (Token::Wildcard, "variable.language.wildcard"),
+            (Token::Error, "invalid.illegal"),
+        ];
+        for (token, scope) in scopes {
+            assert_eq!(highlight_scope(&token), scope, "{:?}", token);
+        }
+    }
+
+    #[test]
+    fn delim_text() {
+        assert_eq!(Delim::Paren.open_str(), "(");
+        assert_eq!(Delim::Paren.close_str(), ")");
+        assert_eq!(Delim::Block.open_str(), "\n");
+        assert_eq!(Delim::Block.close_str(), "");
+        // Every delimiter with source characters spells them the same way
+        for delim in [Delim::Paren, Delim::Bracket, Delim::Brace, Delim::Angle] {
+            let (open, close) = delim.chars().unwrap();
+            assert_eq!(delim.open_str(), open.to_string());
+            assert_eq!(delim.close_str(), close.to_string());
+        }
+    }
+
+    #[test]
+    fn binding_powers() {
+        let power = |symbol: Symbol| symbol.binding_power().unwrap();
+        let tighter_first = [
+            Symbol::Times,
+            Symbol::Plus,
+            Symbol::Concat,
+            Symbol::Eq,
+            Symbol::And,
+        ];
+        for pair in tighter_first.windows(2) {
+            assert!(power(pair[0]).0 > power(pair[1]).1, "{:?}", pair);
+        }
+        // Left associative: in `a - b - c`, the first `-` holds on to `b` more tightly
+        // than the second one can pull it
+        for symbol in tighter_first {
+            let (left, right) = power(symbol);
+            assert!(left < right, "{:?}", symbol);
         }
+        assert_eq!(Symbol::Assign.binding_power(), None);
     }
 }
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use chumsky::span::SimpleSpan;
+
+use crate::ast::{ItemPath, TypeName, TypeSig};
+
+/// The structs and enums in scope, keyed by the path they're named with.
+#[derive(Default)]
+pub struct TypeEnv {
+    types: HashMap<ItemPath, TypeSig>,
+}
+
+impl TypeEnv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn declare(&mut self, path: ItemPath, sig: TypeSig) {
+        self.types.insert(path, sig);
+    }
+
+    pub fn get(&self, path: &ItemPath) -> Option<&TypeSig> {
+        self.types.get(path)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolveError {
+    // A named type that isn't declared in the environment
+    UnknownType(SimpleSpan),
+    // `Self` outside of an impl or method
+    UnboundSelf,
+}
+
+impl TypeName {
+    /// Resolves a type as written to the structural type it denotes.
+    pub fn resolve(&self, env: &TypeEnv) -> Result<TypeSig, ResolveError> {
+        Ok(match self {
+            TypeName::Unit => TypeSig::Unit,
+            TypeName::Int => TypeSig::Int,
+            TypeName::Float => TypeSig::Float,
+            TypeName::String => TypeSig::String,
+            TypeName::Bool => TypeSig::Bool,
+            TypeName::Tuple(items) => TypeSig::Tuple(
+                items
+                    .iter()
+                    .map(|item| item.resolve(env))
+                    .collect::<Result<_, _>>()?,
+            ),
+            TypeName::List(item) => TypeSig::List(Box::new(item.resolve(env)?)),
+            TypeName::Func(args, ret) => TypeSig::Func(
+                args.iter()
+                    .map(|arg| arg.resolve(env))
+                    .collect::<Result<_, _>>()?,
+                Box::new(match ret {
+                    Some(ret) => ret.resolve(env)?,
+                    None => TypeSig::Unit,
+                }),
+            ),
+            TypeName::Optional(inner) => TypeSig::Optional(Box::new(inner.resolve(env)?)),
+            TypeName::Named(path) => env.get(path).cloned().ok_or_else(|| {
+                let start = path.items.first().map_or(0, |(_, span)| span.start);
+                let end = path.items.last().map_or(start, |(_, span)| span.end);
+                ResolveError::UnknownType(SimpleSpan::new(start, end))
+            })?,
+            TypeName::SelfType => return Err(ResolveError::UnboundSelf),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::PathPart;
+    use lasso::{Rodeo, Spur};
+
+    fn named(name: Spur, at: usize) -> ItemPath {
+        ItemPath {
+            items: vec![(PathPart::Name(name), SimpleSpan::new(at, at + 6))],
+        }
+    }
+
+    #[test]
+    fn list_of_int() {
+        let ty = TypeName::List(Box::new(TypeName::Int));
+        assert!(ty.resolve(&TypeEnv::new()) == Ok(TypeSig::List(Box::new(TypeSig::Int))));
+    }
+
+    #[test]
+    fn declared_struct() {
+        let mut rodeo = Rodeo::new();
+        let (person, name) = (rodeo.get_or_intern("Person"), rodeo.get_or_intern("name"));
+        let sig = TypeSig::Struct(vec![(name, TypeSig::String)]);
+        let mut env = TypeEnv::new();
+        env.declare(named(person, 0), sig.clone());
+
+        // Looked up by path, wherever it's written
+        let ty = TypeName::Optional(Box::new(TypeName::Named(named(person, 30))));
+        assert!(ty.resolve(&env) == Ok(TypeSig::Optional(Box::new(sig))));
+    }
+
+    #[test]
+    fn unknown_type() {
+        let mut rodeo = Rodeo::new();
+        let person = rodeo.get_or_intern("Person");
+        let ty = TypeName::List(Box::new(TypeName::Named(named(person, 12))));
+        assert_eq!(
+            ty.resolve(&TypeEnv::new()).err(),
+            Some(ResolveError::UnknownType(SimpleSpan::new(12, 18)))
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use chumsky::span::SimpleSpan;
+use slotmap::{Key, KeyData};
+
+use crate::{
+    ast::{Arg, Block, Expr, Module, Param},
+    bump::{BumpMap, Node, NodeIds},
+    Spanned,
+};
+
+/// Hooks called while walking an AST. Both are called on the way down, so a
+/// parent is always seen before its children.
+pub trait Visitor {
+    fn node(&mut self, _node: KeyData) {}
+    fn expr(&mut self, _expr: &Expr, _span: SimpleSpan, _nodes: &BumpMap) {}
+}
+
+/// Walks an AST in source order, following `Node` handles through the `BumpMap`.
+pub trait Walk {
+    fn walk(&self, nodes: &BumpMap, visitor: &mut impl Visitor);
+}
+
+impl<T: Walk + 'static> Walk for Node<T> {
+    fn walk(&self, nodes: &BumpMap, visitor: &mut impl Visitor) {
+        visitor.node(self.data());
+        if let Some(value) = nodes.get(*self) {
+            value.walk(nodes, visitor);
+        }
+    }
+}
+
+impl<T: Walk> Walk for Option<T> {
+    fn walk(&self, nodes: &BumpMap, visitor: &mut impl Visitor) {
+        if let Some(value) = self {
+            value.walk(nodes, visitor);
+        }
+    }
+}
+
+impl<T: Walk> Walk for Vec<T> {
+    fn walk(&self, nodes: &BumpMap, visitor: &mut impl Visitor) {
+        for value in self {
+            value.walk(nodes, visitor);
+        }
+    }
+}
+
+impl Walk for Spanned<Block> {
+    fn walk(&self, nodes: &BumpMap, visitor: &mut impl Visitor) {
+        self.0.stmts.walk(nodes, visitor);
+        self.0.tail.walk(nodes, visitor);
+    }
+}
+
+impl Walk for Param {
+    fn walk(&self, nodes: &BumpMap, visitor: &mut impl Visitor) {
+        self.default.walk(nodes, visitor);
+    }
+}
+
+impl Walk for Arg {
+    fn walk(&self, nodes: &BumpMap, visitor: &mut impl Visitor) {
+        match self {
+            Arg::Positional(expr) | Arg::Named(_, expr) => expr.walk(nodes, visitor),
+        }
+    }
+}
+
+impl Walk for Spanned<Expr> {
+    fn walk(&self, nodes: &BumpMap, visitor: &mut impl Visitor) {
+        visitor.expr(&self.0, self.1, nodes);
+        match &self.0 {
+            Expr::Let(l) => {
+                l.pat.walk(nodes, visitor);
+                l.init.walk(nodes, visitor);
+            }
+            Expr::If(i) => {
+                i.cond.walk(nodes, visitor);
+                i.body.walk(nodes, visitor);
+                i.alt.walk(nodes, visitor);
+            }
+            Expr::While(w) => {
+                w.cond.walk(nodes, visitor);
+                w.body.walk(nodes, visitor);
+            }
+            Expr::For(f) => {
+                f.item.walk(nodes, visitor);
+                f.iter.walk(nodes, visitor);
+                f.body.walk(nodes, visitor);
+            }
+            Expr::Loop(l) => l.body.walk(nodes, visitor),
+            Expr::With(w) => {
+                w.value.walk(nodes, visitor);
+                w.body.walk(nodes, visitor);
+            }
+            Expr::Break(b) => b.value.walk(nodes, visitor),
+            Expr::Return(value) => value.walk(nodes, visitor),
+            Expr::Paren(expr) => expr.walk(nodes, visitor),
+            Expr::FuncDecl(f) => {
+                f.args.walk(nodes, visitor);
+                f.body.walk(nodes, visitor);
+            }
+            Expr::AnonFunc(f) => {
+                f.args.walk(nodes, visitor);
+                f.body.walk(nodes, visitor);
+            }
+            Expr::Method(m) => {
+                m.args.walk(nodes, visitor);
+                m.body.walk(nodes, visitor);
+            }
+            Expr::StructInit(s) => {
+                s.base.walk(nodes, visitor);
+                for field in &s.fields {
+                    field.value.walk(nodes, visitor);
+                }
+            }
+            Expr::ListInit(l) => l.items.walk(nodes, visitor),
+            Expr::List(items) => items.walk(nodes, visitor),
+            Expr::Binary(b) => {
+                b.lhs.walk(nodes, visitor);
+                b.rhs.walk(nodes, visitor);
+            }
+            Expr::Unary(u) => u.expr.walk(nodes, visitor),
+            Expr::Call(c) => {
+                c.func.walk(nodes, visitor);
+                c.args.walk(nodes, visitor);
+            }
+            Expr::Access(a) => a.expr.walk(nodes, visitor),
+            Expr::Index(i) => {
+                i.expr.walk(nodes, visitor);
+                i.index.walk(nodes, visitor);
+            }
+            Expr::Assign(a) => {
+                a.target.walk(nodes, visitor);
+                a.value.walk(nodes, visitor);
+            }
+            Expr::Import(_)
+            | Expr::Continue
+            | Expr::StructDef(_)
+            | Expr::SelfRef
+            | Expr::Ident(_)
+            | Expr::Wildcard
+            | Expr::Int(_)
+            | Expr::Float(_)
+            | Expr::String(_)
+            | Expr::Bool(_) => {}
+        }
+    }
+}
+
+impl Visitor for NodeIds {
+    fn node(&mut self, node: KeyData) {
+        self.push(node);
+    }
+}
+
+/// Numbers every node reachable from `module` in pre-order, so that two parses of
+/// the same source assign the same id to the same node.
+pub fn number_nodes(module: &Module, nodes: &BumpMap) -> NodeIds {
+    let mut ids = NodeIds::default();
+    module.walk(nodes, &mut ids);
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Binary;
+    use crate::bump::NodeId;
+    use crate::token::Symbol;
+
+    // A module holding `1 + 2`, allocating the operands in the given order
+    fn sum(nodes: &mut BumpMap, rhs_first: bool) -> (Module, [Node<Spanned<Expr>>; 3]) {
+        let span = SimpleSpan::new(0, 5);
+        let (lhs, rhs) = if rhs_first {
+            let rhs = nodes.insert((Expr::Int(2), span));
+            (nodes.insert((Expr::Int(1), span)), rhs)
+        } else {
+            let lhs = nodes.insert((Expr::Int(1), span));
+            (lhs, nodes.insert((Expr::Int(2), span)))
+        };
+        let op = (Symbol::Plus, span);
+        let stmt = nodes.insert((Expr::Binary(Binary { op, lhs, rhs }), span));
+        let block = Block {
+            stmts: vec![stmt],
+            tail: None,
+        };
+        (nodes.insert((block, span)), [stmt, lhs, rhs])
+    }
+
+    #[test]
+    fn ids_ignore_allocation_order() {
+        let mut nodes = BumpMap::new();
+        let (a, a_nodes) = sum(&mut nodes, false);
+        nodes.insert(());
+        let (b, b_nodes) = sum(&mut nodes, true);
+
+        let (a_ids, b_ids) = (number_nodes(&a, &nodes), number_nodes(&b, &nodes));
+        assert_eq!(a_ids.len(), 4);
+        assert_eq!(b_ids.len(), 4);
+        assert_eq!(a_ids.id(a), Some(NodeId(0)));
+        assert_eq!(b_ids.id(b), Some(NodeId(0)));
+        for (a, b) in a_nodes.into_iter().zip(b_nodes) {
+            assert_ne!(a, b);
+            assert_eq!(a_ids.id(a), b_ids.id(b));
+        }
+        assert!(NodeIds::default().is_empty());
+    }
+}